@@ -0,0 +1,936 @@
+//! The syntax tree of an L20n resource.
+//!
+//! `l20n::parse` turns a resource into a list of `Entry`s. Tools such as
+//! linters and editors can inspect them directly, or implement `Visitor` to
+//! be called for each node in turn. `to_source` writes entries back out as
+//! L20n source.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+
+pub use self::EntryKind::*;
+pub use self::ValueKind::*;
+pub use self::AccessType::*;
+pub use self::ExprKind::*;
+pub use self::BinOp::*;
+pub use self::UnOp::*;
+
+/// A position in an L20n resource.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Pos {
+    /// The byte offset from the start of the resource.
+    pub offset: usize,
+    /// The line, starting from 1.
+    pub line: usize,
+    /// The column, starting from 1.
+    pub col: usize,
+}
+
+/// The region of an L20n resource that a node was parsed from, from the
+/// start of its first character to the end of its last.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    /// Where the node starts.
+    pub start: Pos,
+    /// Where the node ends.
+    pub end: Pos,
+}
+
+/// A top-level item of a resource.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Entry {
+    /// What the entry is.
+    pub kind: EntryKind,
+    /// Where the entry was parsed from.
+    pub span: Span,
+}
+
+/// The kinds of Entry.
+#[derive(Debug, PartialEq, Clone)]
+pub enum EntryKind {
+    /// `<id[index] value attr: value>`. Contains the id, the value, the
+    /// index expressions and the attributes.
+    Entity(String, Value, Vec<Expr>, Vec<Attr>),
+    /// `<id($a, $b) { body }>`. Contains the id, the parameters, which are
+    /// `VarExpr`s, and the body.
+    Macro(String, Vec<Expr>, Expr),
+    /// `/* text */`. Contains the text between the delimiters.
+    Comment(String),
+    /// `import('path')`. Contains the path of the resource to import,
+    /// relative to the importing one.
+    Import(String),
+}
+
+/// The value of an entity or attribute.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Value {
+    /// What the value is.
+    pub kind: ValueKind,
+    /// Where the value was parsed from.
+    pub span: Span,
+}
+
+/// The kinds of Value.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ValueKind {
+    /// A string without placeables.
+    Str(String),
+    /// A string with placeables, such as `"Hello, {{ $name }}"`. The literal
+    /// parts are `ValExpr`s holding a `Str`.
+    ComplexStr(Vec<Expr>),
+    /// `{ key: value, *default: value }`. Contains the members, the key
+    /// marked with `*` if any, and the index of a Hash nested in another.
+    Hash(HashMap<String, Value>, Option<String>, Option<Box<Expr>>)
+}
+
+/// How a property or attribute is named in a `PropExpr` or `AttrExpr`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AccessType {
+    /// `a[b]` or `a::[b]`, where `b` is an expression evaluated to a key.
+    Computed,
+    /// `a.b` or `a::b`, where `b` is an `IdentExpr` naming the key.
+    Static,
+}
+
+/// An expression, inside a placeable, an index or a macro body.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expr {
+    /// What the expression is.
+    pub kind: ExprKind,
+    /// Where the expression was parsed from.
+    pub span: Span,
+}
+
+/// The kinds of Expr.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExprKind {
+    /// `cond ? consequent : alternate`.
+    CondExpr(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `left op right`.
+    BinExpr(Box<Expr>, BinOp, Box<Expr>),
+    /// `op expr`.
+    UnExpr(UnOp, Box<Expr>),
+    /// `$name`, a variable from the data or a macro parameter.
+    VarExpr(String),
+    /// A string or Hash literal.
+    ValExpr(Value),
+    /// `parent.prop` or `parent[prop]`.
+    PropExpr(Box<Expr>, Box<Expr>, AccessType),
+    /// `parent::attr` or `parent::[attr]`.
+    AttrExpr(Box<Expr>, Box<Expr>, AccessType),
+    /// `callee(args)`.
+    CallExpr(Box<Expr>, Vec<Expr>),
+    /// `name`, a reference to an entity or macro.
+    IdentExpr(String),
+    /// An integer literal.
+    NumExpr(i64),
+    /// A decimal literal, such as `1.5`.
+    FloatExpr(f64),
+    /// `(expr)`.
+    ParenExpr(Box<Expr>),
+    /// `@name`, a global.
+    GlobalExpr(String),
+    /// `~`, the entity being resolved.
+    ThisExpr,
+}
+
+/// An attribute of an entity, `name[index]: value`. Contains the name, the
+/// value, the index expressions and where it was parsed from.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Attr(pub String, pub Value, pub Vec<Expr>, pub Span);
+
+impl Entry {
+    /// Creates an Entry.
+    pub fn new(kind: EntryKind, span: Span) -> Entry {
+        Entry { kind: kind, span: span }
+    }
+}
+
+impl Value {
+    /// Creates a Value.
+    pub fn new(kind: ValueKind, span: Span) -> Value {
+        Value { kind: kind, span: span }
+    }
+}
+
+impl Expr {
+    /// Creates an Expr.
+    pub fn new(kind: ExprKind, span: Span) -> Expr {
+        Expr { kind: kind, span: span }
+    }
+
+    /// The text of a literal part of a string, such as `Hello, ` in
+    /// `"Hello, {{ $name }}"`.
+    pub fn as_literal(&self) -> Option<&String> {
+        match self.kind {
+            ValExpr(Value { kind: Str(ref s), .. }) => Some(s),
+            _ => None
+        }
+    }
+}
+
+/// The binary operators.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BinOp {
+    /// `+`
+    BiAdd,
+    /// `-`
+    BiSub,
+    /// `*`
+    BiMul,
+    /// `/`
+    BiDiv,
+    /// `%`
+    BiRem,
+    /// `&&`
+    BiAnd,
+    /// `||`
+    BiOr,
+    /// `==`
+    BiEq,
+    /// `!=`
+    BiNe,
+    /// `<`
+    BiLt,
+    /// `<=`
+    BiLe,
+    /// `>`
+    BiGt,
+    /// `>=`
+    BiGe
+}
+
+/// The unary operators.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum UnOp {
+    /// `+`
+    UnAdd,
+    /// `-`
+    UnSub,
+    /// `!`
+    UnNot
+}
+
+/// Called for each node while walking a syntax tree. Every method walks
+/// the node's children by default, so an implementation only overrides the
+/// nodes it's interested in, and calls the matching `walk_*` function to
+/// keep descending.
+///
+/// ```rust
+/// use l20n::ast::{self, Visitor};
+///
+/// struct Vars(Vec<String>);
+///
+/// impl Visitor for Vars {
+///     fn visit_expr(&mut self, expr: &ast::Expr) {
+///         if let ast::VarExpr(ref name) = expr.kind {
+///             self.0.push(name.clone());
+///         }
+///         ast::walk_expr(self, expr);
+///     }
+/// }
+///
+/// let mut vars = Vars(vec![]);
+/// for entry in l20n::parse("<hi 'Hi, {{ $name }}' title: '{{ $title }}'>").unwrap() {
+///     vars.visit_entry(&entry);
+/// }
+/// assert_eq!(vars.0, vec!["name", "title"]);
+/// ```
+pub trait Visitor {
+    /// Called for each Entry.
+    fn visit_entry(&mut self, entry: &Entry) {
+        walk_entry(self, entry)
+    }
+
+    /// Called for each attribute of an entity.
+    fn visit_attr(&mut self, attr: &Attr) {
+        walk_attr(self, attr)
+    }
+
+    /// Called for each Value.
+    fn visit_value(&mut self, value: &Value) {
+        walk_value(self, value)
+    }
+
+    /// Called for each Expr.
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr)
+    }
+}
+
+/// Visits the value, index and attributes of an entity, or the parameters
+/// and body of a macro.
+pub fn walk_entry<V: Visitor + ?Sized>(visitor: &mut V, entry: &Entry) {
+    match entry.kind {
+        Entity(_, ref value, ref index, ref attrs) => {
+            visitor.visit_value(value);
+            for expr in index {
+                visitor.visit_expr(expr);
+            }
+            for attr in attrs {
+                visitor.visit_attr(attr);
+            }
+        }
+        Macro(_, ref params, ref body) => {
+            for param in params {
+                visitor.visit_expr(param);
+            }
+            visitor.visit_expr(body);
+        }
+        Comment(_) | Import(_) => {}
+    }
+}
+
+/// Visits the value and index of an attribute.
+pub fn walk_attr<V: Visitor + ?Sized>(visitor: &mut V, attr: &Attr) {
+    visitor.visit_value(&attr.1);
+    for expr in &attr.2 {
+        visitor.visit_expr(expr);
+    }
+}
+
+/// Visits the placeables of a string, or the members of a Hash in the
+/// order of their keys followed by its index.
+pub fn walk_value<V: Visitor + ?Sized>(visitor: &mut V, value: &Value) {
+    match value.kind {
+        Str(_) => {}
+        ComplexStr(ref exprs) => for expr in exprs {
+            visitor.visit_expr(expr);
+        },
+        Hash(ref map, _, ref index) => {
+            let mut keys = map.keys().collect::<Vec<_>>();
+            keys.sort();
+            for key in keys {
+                visitor.visit_value(&map[key]);
+            }
+            if let Some(ref expr) = *index {
+                visitor.visit_expr(expr);
+            }
+        }
+    }
+}
+
+/// Visits the subexpressions of an expression. The name in a `Static`
+/// property or attribute access, such as `b` in `a.b`, isn't a reference
+/// to an entity, so it isn't visited.
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr.kind {
+        CondExpr(ref cond, ref consequent, ref alt) => {
+            visitor.visit_expr(cond);
+            visitor.visit_expr(consequent);
+            visitor.visit_expr(alt);
+        }
+        BinExpr(ref left, _, ref right) => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        UnExpr(_, ref expr) | ParenExpr(ref expr) => visitor.visit_expr(expr),
+        ValExpr(ref value) => visitor.visit_value(value),
+        PropExpr(ref parent, ref prop, access) | AttrExpr(ref parent, ref prop, access) => {
+            visitor.visit_expr(parent);
+            if access == Computed {
+                visitor.visit_expr(prop);
+            }
+        }
+        CallExpr(ref callee, ref args) => {
+            visitor.visit_expr(callee);
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+        VarExpr(_) | IdentExpr(_) | NumExpr(_) | FloatExpr(_) | GlobalExpr(_) | ThisExpr => {}
+    }
+}
+
+/// Writes entries back to L20n source, one per line, in the order given.
+/// Comments are kept, and entries that were separated by a blank line still
+/// are. Parsing the result gives back the same entries, once `normalized`.
+///
+/// ```rust
+/// let src = "/* Shown in the title bar. */\n<title \"Rust\"\n  short: \"R\">\n";
+/// let entries = l20n::parse(src).unwrap();
+/// assert_eq!(l20n::ast::to_source(&entries), src);
+/// ```
+pub fn to_source(entries: &[Entry]) -> String {
+    let mut out = String::new();
+    let mut last_line = None;
+    for entry in entries {
+        if let Some(line) = last_line {
+            if entry.span.start.line > line + 1 {
+                out.push('\n');
+            }
+        }
+        out.push_str(&entry.to_string());
+        out.push('\n');
+        last_line = Some(entry.span.end.line);
+    }
+    out
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Entity(ref id, ref value, ref index, ref attrs) => {
+                try!(write!(f, "<{}", id));
+                try!(write_index(f, index));
+                try!(write!(f, " {}", value));
+                for attr in attrs {
+                    try!(write!(f, "\n  {}", attr));
+                }
+                write!(f, ">")
+            }
+            Macro(ref id, ref params, ref body) => {
+                try!(write!(f, "<{}(", id));
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(f, ", "));
+                    }
+                    try!(write!(f, "{}", param));
+                }
+                write!(f, ") {{ {} }}>", body)
+            }
+            Comment(ref text) => write!(f, "/*{}*/", text),
+            Import(ref path) => {
+                let quote = if path.contains('"') { "'" } else { "\"" };
+                write!(f, "import({}{}{})", quote, path, quote)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Attr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.0));
+        try!(write_index(f, &self.2));
+        write!(f, ": {}", self.1)
+    }
+}
+
+fn write_index(f: &mut fmt::Formatter, index: &[Expr]) -> fmt::Result {
+    if index.is_empty() {
+        return Ok(());
+    }
+    try!(write!(f, "["));
+    for (i, expr) in index.iter().enumerate() {
+        if i > 0 {
+            try!(write!(f, ", "));
+        }
+        try!(write!(f, "{}", expr));
+    }
+    write!(f, "]")
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl ValueKind {
+    // The literal text of a string, and its placeables, in order.
+    fn parts<'a>(&'a self) -> Vec<Part<'a>> {
+        match *self {
+            Str(ref s) => vec![Part::Text(s)],
+            ComplexStr(ref exprs) => exprs.iter().map(|e| match e.as_literal() {
+                Some(s) => Part::Text(s),
+                None => Part::Placeable(e)
+            }).collect(),
+            Hash(..) => vec![]
+        }
+    }
+
+    /// The text of a string with its placeables in `{{ }}`, and the quote
+    /// to triple-quote it with, if it reads back exactly as a triple-quoted
+    /// string. The parser drops whitespace at the start of each line and at
+    /// the end of such strings, so strings with any there don't. None for
+    /// hashes.
+    #[doc(hidden)]
+    pub fn tristr(&self) -> Option<(String, &'static str)> {
+        if let Hash(..) = *self {
+            return None;
+        }
+        let text = self.parts().iter().map(|part| match *part {
+            Part::Text(s) => s.to_string(),
+            Part::Placeable(e) => format!("{{{{ {} }}}}", e)
+        }).collect::<String>();
+        let blank = |c: Option<char>| c.map_or(false, char::is_whitespace);
+        if blank(text.chars().next()) || blank(text.chars().last()) ||
+           text.contains("\n ") || text.contains("\n\t") {
+            return None;
+        }
+        if !text.contains("\"\"\"") {
+            Some((text, "\"\"\""))
+        } else if !text.contains("'''") {
+            Some((text, "'''"))
+        } else {
+            None
+        }
+    }
+}
+
+enum Part<'a> {
+    Text(&'a str),
+    Placeable(&'a Expr),
+}
+
+impl fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Str(_) | ComplexStr(_) => {
+                let parts = self.parts();
+                let has = |c| parts.iter().any(|part| match *part {
+                    Part::Text(s) => s.contains(c),
+                    Part::Placeable(_) => false
+                });
+                if has('\n') || (has('"') && has('\'')) {
+                    if let Some((text, quote)) = self.tristr() {
+                        // The parser drops the space that keeps a closing
+                        // quote in the text from ending the string early.
+                        let pad = if text.ends_with(&quote[..1]) { " " } else { "" };
+                        return write!(f, "{}{}{}{}", quote, text, pad, quote);
+                    }
+                }
+                // Plain strings have no escapes, so a string with both
+                // quotes gets its double quotes from placeables.
+                let quote = if has('"') && !has('\'') { "'" } else { "\"" };
+                try!(write!(f, "{}", quote));
+                for part in &parts {
+                    match *part {
+                        Part::Text(s) if quote == "\"" => try!(write!(f, "{}", s.replace('"', "{{ '\"' }}"))),
+                        Part::Text(s) => try!(write!(f, "{}", s)),
+                        Part::Placeable(e) => try!(write!(f, "{{{{ {} }}}}", e))
+                    }
+                }
+                write!(f, "{}", quote)
+            }
+            Hash(ref map, ref default, _) => {
+                let mut keys = map.keys().collect::<Vec<_>>();
+                keys.sort();
+                try!(write!(f, "{{ "));
+                for (i, key) in keys.into_iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(f, ", "));
+                    }
+                    if default.as_ref() == Some(key) {
+                        try!(write!(f, "*"));
+                    }
+                    try!(write!(f, "{}: {}", key, map[key]));
+                }
+                write!(f, " }}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl ExprKind {
+    // How tightly the expression binds, following the parser: a higher
+    // precedence binds more tightly.
+    fn precedence(&self) -> u8 {
+        match *self {
+            CondExpr(..) => 1,
+            BinExpr(_, op, _) => match op {
+                BiOr => 2,
+                BiAnd => 3,
+                BiEq | BiNe => 4,
+                BiLt | BiLe | BiGt | BiGe => 5,
+                BiAdd | BiSub => 6,
+                BiRem => 7,
+                BiMul | BiDiv => 8,
+            },
+            UnExpr(..) => 9,
+            _ => 10
+        }
+    }
+}
+
+// Writes `expr`, in parentheses if it binds less tightly than `min`, so
+// that trees built by hand print as they would parse.
+fn fmt_operand(f: &mut fmt::Formatter, expr: &Expr, min: u8) -> fmt::Result {
+    if expr.kind.precedence() < min {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+impl fmt::Display for ExprKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prec = self.precedence();
+        match *self {
+            CondExpr(ref cond, ref consequent, ref alt) => {
+                try!(fmt_operand(f, cond, prec + 1));
+                write!(f, " ? {} : {}", consequent, alt)
+            }
+            // Binary operators are left associative.
+            BinExpr(ref left, ref op, ref right) => {
+                try!(fmt_operand(f, left, prec));
+                try!(write!(f, " {} ", op));
+                fmt_operand(f, right, prec + 1)
+            }
+            UnExpr(ref op, ref expr) => {
+                try!(write!(f, "{}", op));
+                fmt_operand(f, expr, prec)
+            }
+            VarExpr(ref name) => write!(f, "${}", name),
+            ValExpr(ref val) => write!(f, "{}", val),
+            PropExpr(ref parent, ref prop, Computed) => {
+                try!(fmt_operand(f, parent, prec));
+                write!(f, "[{}]", prop)
+            }
+            PropExpr(ref parent, ref prop, Static) => {
+                try!(fmt_operand(f, parent, prec));
+                write!(f, ".{}", prop)
+            }
+            AttrExpr(ref parent, ref prop, Computed) => {
+                try!(fmt_operand(f, parent, prec));
+                write!(f, "::[{}]", prop)
+            }
+            AttrExpr(ref parent, ref prop, Static) => {
+                try!(fmt_operand(f, parent, prec));
+                write!(f, "::{}", prop)
+            }
+            CallExpr(ref callee, ref args) => {
+                try!(fmt_operand(f, callee, prec));
+                try!(write!(f, "("));
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        try!(write!(f, ", "));
+                    }
+                    try!(write!(f, "{}", arg));
+                }
+                write!(f, ")")
+            }
+            IdentExpr(ref name) => write!(f, "{}", name),
+            NumExpr(n) => write!(f, "{}", n),
+            // Always print a decimal point, so it parses back as a float.
+            FloatExpr(n) if n.fract() == 0.0 => write!(f, "{:.1}", n),
+            FloatExpr(n) => write!(f, "{}", n),
+            ParenExpr(ref expr) => write!(f, "({})", expr),
+            GlobalExpr(ref name) => write!(f, "@{}", name),
+            ThisExpr => write!(f, "~"),
+        }
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            BiAdd => "+",
+            BiSub => "-",
+            BiMul => "*",
+            BiDiv => "/",
+            BiRem => "%",
+            BiAnd => "&&",
+            BiOr => "||",
+            BiEq => "==",
+            BiNe => "!=",
+            BiLt => "<",
+            BiLe => "<=",
+            BiGt => ">",
+            BiGe => ">=",
+        })
+    }
+}
+
+impl fmt::Display for UnOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            UnAdd => "+",
+            UnSub => "-",
+            UnNot => "!",
+        })
+    }
+}
+
+/// Clears the spans of parsed entries, so tests can compare them with
+/// entries built by hand, or parsed from a source laid out differently.
+#[cfg(test)]
+pub fn without_spans(mut entries: Vec<Entry>) -> Vec<Entry> {
+    clear_entries(&mut entries, false);
+    entries
+}
+
+/// Clears the spans of entries, along with anything else that depends only
+/// on how they were written: parentheses, and how the text of a string is
+/// split between literals and string placeables such as `{{ "\"" }}`.
+/// Entries that mean the same compare equal once normalized.
+///
+/// ```rust
+/// use l20n::ast::normalized;
+///
+/// let a = l20n::parse("<hi 'Say \"hi\"'>\n<sum '{{ $a + $b * 2 }}'>").unwrap();
+/// let b = l20n::parse("<hi \"Say {{ '\"' }}hi{{ '\"' }}\"> <sum '{{ $a + ($b * 2) }}'>").unwrap();
+/// assert_eq!(normalized(a), normalized(b));
+/// ```
+pub fn normalized(mut entries: Vec<Entry>) -> Vec<Entry> {
+    clear_entries(&mut entries, true);
+    entries
+}
+
+fn clear_entries(entries: &mut [Entry], normalize: bool) {
+    for entry in entries {
+        entry.span = Span::default();
+        match entry.kind {
+            Entity(_, ref mut value, ref mut index, ref mut attrs) => {
+                clear_value(value, normalize);
+                for expr in index.iter_mut() {
+                    clear_expr(expr, normalize);
+                }
+                for attr in attrs.iter_mut() {
+                    attr.3 = Span::default();
+                    clear_value(&mut attr.1, normalize);
+                    for expr in attr.2.iter_mut() {
+                        clear_expr(expr, normalize);
+                    }
+                }
+            }
+            Macro(_, ref mut params, ref mut body) => {
+                for param in params.iter_mut() {
+                    clear_expr(param, normalize);
+                }
+                clear_expr(body, normalize);
+            }
+            Comment(_) | Import(_) => {}
+        }
+    }
+}
+
+fn clear_value(value: &mut Value, normalize: bool) {
+    value.span = Span::default();
+    match value.kind {
+        Str(_) => {}
+        ComplexStr(ref mut exprs) => for expr in exprs.iter_mut() {
+            clear_expr(expr, normalize);
+        },
+        Hash(ref mut map, _, ref mut index) => {
+            for member in map.values_mut() {
+                clear_value(member, normalize);
+            }
+            if let Some(ref mut expr) = *index {
+                clear_expr(expr, normalize);
+            }
+        }
+    }
+    if normalize {
+        join_literals(value);
+    }
+}
+
+// Joins the literal parts of a string, leaving a Str if it has no other
+// placeables.
+fn join_literals(value: &mut Value) {
+    let exprs = match value.kind {
+        ComplexStr(ref mut exprs) => mem::replace(exprs, vec![]),
+        _ => return
+    };
+    let mut parts = vec![];
+    let mut text = String::new();
+    for expr in exprs {
+        match expr.as_literal() {
+            Some(s) => {
+                text.push_str(s);
+                continue;
+            }
+            None => {}
+        }
+        if !text.is_empty() {
+            parts.push(literal(mem::replace(&mut text, String::new())));
+        }
+        parts.push(expr);
+    }
+    value.kind = if parts.is_empty() {
+        Str(text)
+    } else {
+        if !text.is_empty() {
+            parts.push(literal(text));
+        }
+        ComplexStr(parts)
+    };
+}
+
+fn literal(text: String) -> Expr {
+    Expr::new(ValExpr(Value::new(Str(text), Span::default())), Span::default())
+}
+
+fn clear_expr(expr: &mut Expr, normalize: bool) {
+    if normalize {
+        if let ParenExpr(_) = expr.kind {
+            *expr = match mem::replace(&mut expr.kind, ThisExpr) {
+                ParenExpr(inner) => *inner,
+                _ => unreachable!()
+            };
+            return clear_expr(expr, normalize);
+        }
+    }
+    expr.span = Span::default();
+    match expr.kind {
+        CondExpr(ref mut cond, ref mut consequent, ref mut alt) => {
+            clear_expr(cond, normalize);
+            clear_expr(consequent, normalize);
+            clear_expr(alt, normalize);
+        }
+        BinExpr(ref mut left, _, ref mut right) |
+        PropExpr(ref mut left, ref mut right, _) |
+        AttrExpr(ref mut left, ref mut right, _) => {
+            clear_expr(left, normalize);
+            clear_expr(right, normalize);
+        }
+        UnExpr(_, ref mut inner) | ParenExpr(ref mut inner) => clear_expr(inner, normalize),
+        ValExpr(ref mut value) => clear_value(value, normalize),
+        CallExpr(ref mut callee, ref mut args) => {
+            clear_expr(callee, normalize);
+            for arg in args.iter_mut() {
+                clear_expr(arg, normalize);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalized, to_source, walk_expr, without_spans, Expr, IdentExpr, Visitor};
+    use super::{BiAdd, BiMul, BiOr, BiSub, BinExpr, CondExpr, ComplexStr, Entity, ExprKind};
+    use super::{PropExpr, Span, Static, UnExpr, UnSub, VarExpr};
+    use parser::Parser;
+
+    struct Idents(Vec<String>);
+
+    impl Visitor for Idents {
+        fn visit_expr(&mut self, expr: &Expr) {
+            if let IdentExpr(ref name) = expr.kind {
+                self.0.push(name.clone());
+            }
+            walk_expr(self, expr);
+        }
+    }
+
+    #[test]
+    fn test_visitor() {
+        let src = "<x[i] '{{ a.b }} {{ c::d }} {{ e[f] }} {{ g(h) }}' y: { k: '{{ j }}' }>";
+        let entries = Parser::new(src.chars()).parse().unwrap();
+        let mut idents = Idents(vec![]);
+        idents.visit_entry(&entries[0]);
+        assert_eq!(idents.0, vec!["a", "c", "e", "f", "g", "h", "i", "j"]);
+    }
+
+    #[test]
+    fn test_to_source() {
+        let src = r#"/* Branding */
+<brand "Rust"
+  short: 'R'>
+/**
+ * Greetings
+ */
+
+<hi[@hour < 12 ? "morning" : "other"] { *morning: "Hi, {{ $user.name }}!", other: 'Say "hi"' }>
+<empty "">
+<add($a, $b) { ($a + $b) * -2.0 }>
+<sum "{{ add(1, 2) }}{{ brand::short }}{{ brand::[key] }}"
+  long[$n]: """
+    Line one
+    Line two""">
+"#;
+        let entries = Parser::new(src.chars()).parse().unwrap();
+        let out = to_source(&entries);
+        assert_eq!(out, r#"/* Branding */
+<brand "Rust"
+  short: "R">
+/**
+ * Greetings
+ */
+
+<hi[@hour < 12 ? "morning" : "other"] { *morning: "Hi, {{ $user.name }}!", other: 'Say "hi"' }>
+<empty "">
+<add($a, $b) { ($a + $b) * -2.0 }>
+<sum "{{ add(1, 2) }}{{ brand::short }}{{ brand::[key] }}"
+  long[$n]: """Line one
+Line two""">
+"#);
+        assert_eq!(without_spans(Parser::new(out.chars()).parse().unwrap()), without_spans(entries));
+    }
+
+    fn expr(kind: ExprKind) -> Box<Expr> {
+        Box::new(Expr::new(kind, Span::default()))
+    }
+
+    fn var(name: &str) -> Box<Expr> {
+        expr(VarExpr(String::from(name)))
+    }
+
+    fn parse_expr(src: &str) -> Expr {
+        let src = format!("<x '{{{{ {} }}}}'>", src);
+        // Normalized, as the parser keeps parentheses as nodes, which a tree
+        // built by hand doesn't have.
+        let mut entries = normalized(Parser::new(src.chars()).parse().unwrap());
+        match entries.remove(0).kind {
+            Entity(_, value, _, _) => match value.kind {
+                ComplexStr(mut exprs) => exprs.remove(0),
+                other => panic!("expected a placeable, got {:?}", other)
+            },
+            other => panic!("expected an entity, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_display_precedence() {
+        let a_plus_b = expr(BinExpr(var("a"), BiAdd, var("b")));
+        let cases = vec![
+            (BinExpr(a_plus_b.clone(), BiMul, var("c")), "($a + $b) * $c"),
+            (BinExpr(var("c"), BiMul, a_plus_b.clone()), "$c * ($a + $b)"),
+            (BinExpr(a_plus_b.clone(), BiAdd, var("c")), "$a + $b + $c"),
+            (BinExpr(var("c"), BiSub, a_plus_b.clone()), "$c - ($a + $b)"),
+            (BinExpr(var("c"), BiOr, a_plus_b.clone()), "$c || $a + $b"),
+            (UnExpr(UnSub, a_plus_b.clone()), "-($a + $b)"),
+            (PropExpr(a_plus_b.clone(), expr(IdentExpr(String::from("c"))), Static), "($a + $b).c"),
+            (CondExpr(expr(CondExpr(var("a"), var("b"), var("c"))), var("d"), var("e")),
+             "($a ? $b : $c) ? $d : $e"),
+            (CondExpr(var("a"), var("b"), expr(CondExpr(var("c"), var("d"), var("e")))),
+             "$a ? $b : $c ? $d : $e"),
+        ];
+        for (kind, src) in cases {
+            let e = Expr::new(kind, Span::default());
+            assert_eq!(e.to_string(), src);
+            assert_eq!(parse_expr(src), e);
+        }
+    }
+
+    #[test]
+    fn test_to_source_round_trip() {
+        let cases = [
+            r#"<a """it's "x" """>"#,
+            r#"<b '''it's "x"'''>"#,
+            "<c \"  lead\nsecond\">",
+            "<d 'first\n  indented'>",
+            "<e \"\nstarts with a line break\">",
+            "<f \"ends with a line break\n\">",
+            r#"<g """{{ $n }} isn't "{{ $m }}" """>"#,
+            r#"<h """  it's "x" """>"#,
+            r#"<i "{{ '"""' }} and {{ "'''" }}">"#,
+            r#"<j { one: """it's "x" """, *other: "  a\nb" } k: "  {{ $n }}\n">"#,
+        ];
+        for src in cases.iter() {
+            let entries = Parser::new(src.chars()).parse().unwrap();
+            let out = to_source(&entries);
+            let reparsed = match Parser::new(out.chars()).parse() {
+                Ok(reparsed) => reparsed,
+                Err(e) => panic!("{:?} printed as {:?}, which fails to parse: {:?}", src, out, e)
+            };
+            assert_eq!(normalized(reparsed), normalized(entries), "{:?} printed as {:?}", src, out);
+            assert_eq!(to_source(&Parser::new(out.chars()).parse().unwrap()), out);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Formats L20n resources in the canonical style. See `l20n::format`.
+
+extern crate l20n;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+const USAGE: &'static str = "Usage: l20n-fmt [--check] [FILE]...
+
+Formats L20n resources in place. With no FILE, formats standard input to
+standard output. Exits with status 2 if a resource can't be read or parsed.
+
+Options:
+    --check    Don't change any files. List the ones that aren't formatted,
+               and exit with status 1 if there are any.
+    -h, --help Print this message.";
+
+fn main() {
+    let mut check = false;
+    let mut paths = vec![];
+    for arg in env::args().skip(1) {
+        match &*arg {
+            "--check" => check = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with('-') => fail(&format!("unknown option {}\n\n{}", arg, USAGE)),
+            _ => paths.push(arg)
+        }
+    }
+
+    if paths.is_empty() {
+        let mut source = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut source) {
+            fail(&format!("<stdin>: {}", e));
+        }
+        let formatted = match l20n::format(&source) {
+            Ok(formatted) => formatted,
+            Err(e) => fail(&format!("<stdin>: {}", e))
+        };
+        if check {
+            if formatted != source {
+                println!("<stdin>");
+                process::exit(1);
+            }
+        } else {
+            print!("{}", formatted);
+        }
+        return;
+    }
+
+    let mut unformatted = false;
+    let mut failed = false;
+    for path in &paths {
+        match format_file(path, check) {
+            Ok(true) => {}
+            Ok(false) => {
+                println!("{}", path);
+                unformatted = true;
+            }
+            Err(e) => {
+                let _ = writeln!(io::stderr(), "{}: {}", path, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(2);
+    }
+    if unformatted {
+        process::exit(1);
+    }
+}
+
+// Returns whether the file was already formatted. Unless `check` is set,
+// it is rewritten if it wasn't.
+fn format_file(path: &str, check: bool) -> Result<bool, String> {
+    let mut source = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut source)) {
+        return Err(e.to_string());
+    }
+    let formatted = match l20n::format(&source) {
+        Ok(formatted) => formatted,
+        Err(e) => return Err(e.to_string())
+    };
+    if formatted == source {
+        return Ok(true);
+    }
+    if !check {
+        if let Err(e) = File::create(path).and_then(|mut f| f.write_all(formatted.as_bytes())) {
+            return Err(e.to_string());
+        }
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+fn fail(msg: &str) -> ! {
+    let _ = writeln!(io::stderr(), "l20n-fmt: {}", msg);
+    process::exit(2);
+}
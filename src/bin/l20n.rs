@@ -0,0 +1,210 @@
+//! Evaluates and inspects L20n resources from the command line.
+
+extern crate l20n;
+extern crate serde_json;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process;
+
+use l20n::{Data, Locale};
+use l20n::compare::DifferenceKind;
+
+const USAGE: &'static str = "Usage: l20n [--locale TAG] <COMMAND> FILE [ARGS]
+
+Commands:
+    eval FILE [DATA]        Print every entity in FILE, resolved with the
+                            JSON object in the file DATA.
+    parse FILE              Report every error parsing FILE.
+    ast FILE                Print the syntax tree of FILE.
+    lint FILE...            Warn about likely mistakes in the FILEs, which
+                            are linted together as one locale.
+    compare REFERENCE FILE  Report entities, attributes, hash keys and
+                            variables in which the translation FILE differs
+                            from REFERENCE.
+    trace FILE ID [DATA]    Resolve the entity ID, printing each expression
+                            resolved along the way.
+
+Options:
+    --locale TAG            The locale whose plural rules and number and
+                            date formats to use. Defaults to i-default.
+    -h, --help              Print this message.";
+
+fn main() {
+    let mut tag = String::from("i-default");
+    let mut args = vec![];
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match &*arg {
+            "--locale" => match argv.next() {
+                Some(t) => tag = t,
+                None => fail("--locale needs a TAG")
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with('-') => fail(&format!("unknown option {}\n\n{}", arg, USAGE)),
+            _ => args.push(arg)
+        }
+    }
+
+    let ok = match (args.get(0).map(|s| &s[..]), args.len()) {
+        (Some("eval"), 2) | (Some("eval"), 3) => eval(&tag, &args[1], args.get(2)),
+        (Some("parse"), 2) => parse(&tag, &args[1]),
+        (Some("ast"), 2) => ast(&args[1]),
+        (Some("lint"), n) if n >= 2 => lint(&args[1..]),
+        (Some("compare"), 3) => compare(&args[1], &args[2]),
+        (Some("trace"), 3) | (Some("trace"), 4) => trace(&tag, &args[1], &args[2], args.get(3)),
+        _ => fail(USAGE)
+    };
+    if !ok {
+        process::exit(1);
+    }
+}
+
+fn eval(tag: &str, path: &str, data: Option<&String>) -> bool {
+    let locale = load(tag, path);
+    let data = load_data(data);
+    let strings: HashMap<String, String> = match locale.localize_data(data) {
+        Ok(strings) => strings,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "{}: {}", path, e);
+            return false;
+        }
+    };
+    let mut ids = strings.keys().collect::<Vec<_>>();
+    ids.sort();
+    for id in ids {
+        println!("{}: {}", id, strings[id]);
+    }
+    true
+}
+
+fn parse(tag: &str, path: &str) -> bool {
+    let errors = Locale::with_tag(tag).add_resource_partial(&read(path));
+    for e in &errors {
+        println!("{}: {}", path, e);
+    }
+    errors.is_empty()
+}
+
+fn ast(path: &str) -> bool {
+    match l20n::parse(&read(path)) {
+        Ok(entries) => {
+            println!("{:#?}", entries);
+            true
+        }
+        Err(e) => {
+            println!("{}: {}", path, e);
+            false
+        }
+    }
+}
+
+fn lint(paths: &[String]) -> bool {
+    let results = match l20n::lint::lint_files(paths) {
+        Ok(results) => results,
+        Err(e) => fail(&e.to_string())
+    };
+    let mut clean = true;
+    for (path, warnings) in paths.iter().zip(results) {
+        for w in &warnings {
+            println!("{}: warning[{}]: {}", path, w.code(), w);
+            clean = false;
+        }
+    }
+    clean
+}
+
+fn compare(reference: &str, path: &str) -> bool {
+    let diffs = match l20n::compare::compare_files(reference, path) {
+        Ok(diffs) => diffs,
+        Err(e) => fail(&e.to_string())
+    };
+    for diff in &diffs {
+        // Spans of what's missing point into the reference.
+        let file = match diff.kind {
+            DifferenceKind::MissingEntity | DifferenceKind::MissingAttr(_) => reference,
+            _ => path
+        };
+        println!("{}:{}:{}: {}", file, diff.span.start.line, diff.span.start.col, diff);
+    }
+    diffs.is_empty()
+}
+
+fn trace(tag: &str, path: &str, id: &str, data: Option<&String>) -> bool {
+    let locale = load(tag, path);
+    let (result, steps) = locale.trace(id, load_data(data));
+    for step in &steps {
+        let result = match step.result {
+            Ok(ref d) => show(d),
+            Err(ref e) => format!("error: {}", e)
+        };
+        println!("{}:{}: {}{} => {}", step.span.start.line, step.span.start.col,
+                 "  ".repeat(step.depth), step.expr, result);
+    }
+    match result {
+        Ok(s) => {
+            println!("{}: {}", id, s);
+            true
+        }
+        Err(e) => {
+            println!("{}: error: {}", id, e);
+            false
+        }
+    }
+}
+
+fn read(path: &str) -> String {
+    let mut source = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut source)) {
+        fail(&format!("{}: {}", path, e));
+    }
+    source
+}
+
+fn load(tag: &str, path: &str) -> Locale {
+    let mut locale = Locale::with_tag(tag);
+    if let Err(e) = locale.add_resource_file(path) {
+        fail(&e.to_string());
+    }
+    locale
+}
+
+fn load_data(path: Option<&String>) -> serde_json::Value {
+    match path {
+        Some(path) => match serde_json::from_str(&read(path)) {
+            Ok(value) => value,
+            Err(e) => fail(&format!("{}: {}", path, e))
+        },
+        None => serde_json::Value::Null
+    }
+}
+
+fn show(d: &Data) -> String {
+    match *d {
+        Data::Null => String::from("null"),
+        Data::Bool(b) => b.to_string(),
+        Data::Num(n) => n.to_string(),
+        Data::Float(n) => n.to_string(),
+        Data::DateTime(ref dt) => dt.to_string(),
+        Data::Str(ref s) => format!("{:?}", s),
+        Data::List(ref list) => {
+            format!("[{}]", list.iter().map(show).collect::<Vec<_>>().join(", "))
+        }
+        Data::Map(ref map) => {
+            let mut keys = map.keys().collect::<Vec<_>>();
+            keys.sort();
+            let members = keys.iter().map(|k| format!("{}: {}", k, show(&map[*k]))).collect::<Vec<_>>();
+            format!("{{{}}}", members.join(", "))
+        }
+    }
+}
+
+fn fail(msg: &str) -> ! {
+    let _ = writeln!(io::stderr(), "l20n: {}", msg);
+    process::exit(2);
+}
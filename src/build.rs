@@ -0,0 +1,919 @@
+//! Checking L20n resources while building, from a build script.
+//!
+//! Mistakes like `{{ brnad }}` otherwise only show up as a `ResolveError`
+//! when the string is localized. Add l20n to `[build-dependencies]`, and
+//! check the resources in `build.rs`:
+//!
+//! ```rust,no_run
+//! extern crate l20n;
+//!
+//! fn main() {
+//!     l20n::build::check("locales");
+//! }
+//! ```
+//!
+//! Every `.l20n` file under `locales` is parsed, and the build fails listing
+//! each parse error, reference to an entity, attribute or macro that isn't
+//! defined, and macro called with the wrong number of arguments. The files
+//! in one directory are checked together, as the resources of one locale,
+//! along with the resources they import.
+//!
+//! `generate_file` turns a resource into Rust functions, one per entity, so
+//! that message ids and their arguments are checked by the compiler too.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error;
+use std::error::Error as _StdError;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use ast::{self, Visitor};
+use compiler;
+use context::{self, FileLoader, LoadError};
+use parser::{ParseError, Parser};
+
+/// A problem found in an L20n resource.
+#[derive(Debug)]
+pub struct CheckError {
+    /// The kind of problem.
+    pub kind: CheckErrorKind,
+    /// The line where the problem is.
+    pub line: usize,
+    /// The column where the problem is.
+    pub col: usize,
+}
+
+/// The description of the CheckError that occurred.
+#[derive(Debug)]
+pub enum CheckErrorKind {
+    /// The resource doesn't parse.
+    Parse(ParseError),
+    /// Referenced an entity or macro that isn't defined.
+    UndefinedIdent(String),
+    /// Referenced an attribute that the entity doesn't have. Contains the
+    /// entity and the attribute.
+    UndefinedAttr(String, String),
+    /// Called a macro or function that isn't defined.
+    UndefinedMacro(String),
+    /// Called an entity as if it were a macro.
+    NotAMacro(String),
+    /// Called a macro with the wrong number of arguments. Contains the
+    /// macro, the number of arguments it takes, and the number given.
+    WrongNumberOfArgs(String, usize, usize),
+    /// An imported resource couldn't be loaded.
+    Import(LoadError),
+}
+
+impl error::Error for CheckError {
+    fn description(&self) -> &str {
+        match self.kind {
+            CheckErrorKind::Parse(ref err) => err.description(),
+            CheckErrorKind::UndefinedIdent(_) => "Referenced an entity or macro that is not defined",
+            CheckErrorKind::UndefinedAttr(..) => "Referenced an attribute that is not defined",
+            CheckErrorKind::UndefinedMacro(_) => "Called a macro that is not defined",
+            CheckErrorKind::NotAMacro(_) => "Called something that is not a macro",
+            CheckErrorKind::WrongNumberOfArgs(..) => "A macro was called with the wrong number of arguments",
+            CheckErrorKind::Import(_) => "An imported resource could not be loaded",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match self.kind {
+            CheckErrorKind::Parse(ref err) => Some(err),
+            CheckErrorKind::Import(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            CheckErrorKind::Parse(ref err) => write!(f, "{}", err),
+            CheckErrorKind::UndefinedIdent(ref id) |
+            CheckErrorKind::UndefinedMacro(ref id) |
+            CheckErrorKind::NotAMacro(ref id) => {
+                write!(f, "{}: {} on line {}, column {}", self.description(), id, self.line, self.col)
+            }
+            CheckErrorKind::UndefinedAttr(ref id, ref attr) => {
+                write!(f, "{}: {}::{} on line {}, column {}", self.description(), id, attr, self.line, self.col)
+            }
+            CheckErrorKind::WrongNumberOfArgs(ref id, expected, found) => {
+                write!(f, "{}: {} takes {}, got {} on line {}, column {}",
+                       self.description(), id, expected, found, self.line, self.col)
+            }
+            CheckErrorKind::Import(ref err) => {
+                write!(f, "{}: {} on line {}, column {}", self.description(), err, self.line, self.col)
+            }
+        }
+    }
+}
+
+/// An error generating accessors for an L20n resource.
+#[derive(Debug)]
+pub enum GenerateError {
+    /// The resource doesn't parse.
+    Parse(ParseError),
+    /// Two entities or variables would get the same name in Rust. Contains
+    /// the name and the two ids or variables.
+    Collision(String, String, String),
+}
+
+impl error::Error for GenerateError {
+    fn description(&self) -> &str {
+        match *self {
+            GenerateError::Parse(ref err) => err.description(),
+            GenerateError::Collision(..) => "Two entities or variables have the same name in Rust",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            GenerateError::Parse(ref err) => Some(err),
+            GenerateError::Collision(..) => None,
+        }
+    }
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GenerateError::Parse(ref err) => write!(f, "{}", err),
+            GenerateError::Collision(ref name, ref a, ref b) => {
+                write!(f, "{}: {} and {} are both {}", self.description(), a, b, name)
+            }
+        }
+    }
+}
+
+/// Checks L20n resources for problems that would otherwise only show up
+/// when they are localized.
+pub struct Checker {
+    functions: HashMap<String, Option<usize>>,
+}
+
+impl Checker {
+    /// Creates a Checker that knows about the built-in macros, such as
+    /// `plural`.
+    pub fn new() -> Checker {
+        let mut functions = HashMap::new();
+        for &(name, max) in &[("plural", 1), ("ordinal", 1), ("number", 5),
+                              ("date", 2), ("time", 2), ("datetime", 3)] {
+            functions.insert(String::from(name), Some(max));
+        }
+        Checker {
+            functions: functions
+        }
+    }
+
+    /// Declares a function the application registers with
+    /// `Locale::register_fn`, so that calling it isn't an error.
+    pub fn with_function<S: Into<String>>(mut self, name: S) -> Checker {
+        self.functions.insert(name.into(), None);
+        self
+    }
+
+    /// Checks a single resource, returning its problems in the order they
+    /// appear. There's no file to find its imports from, so if it has any,
+    /// references to entities and macros that aren't defined aren't
+    /// reported, since they may be imported.
+    pub fn check_resource(&self, source: &str) -> Vec<CheckError> {
+        let (env, imports, mut errors) = parse(source);
+        errors.extend(self.check_env(&env, &env, !imports.is_empty()));
+        sort(&mut errors);
+        errors
+    }
+
+    /// Checks the `.l20n` file at `path`, or every one in the directory at
+    /// `path` and its subdirectories. Returns the problems in each file that
+    /// has any.
+    pub fn check_path<P: AsRef<Path>>(&self, path: P) -> io::Result<BTreeMap<PathBuf, Vec<CheckError>>> {
+        let mut locales = BTreeMap::new();
+        try!(collect(path.as_ref(), &mut locales));
+
+        let mut results = BTreeMap::new();
+        for (_, files) in locales {
+            let mut parsed = vec![];
+            let mut merged = compiler::Env::new();
+            for path in files {
+                let mut source = String::new();
+                try!(try!(fs::File::open(&path)).read_to_string(&mut source));
+                let (env, imports, mut errors) = parse(&source);
+                merged.extend(env.iter().map(|(k, v)| (k.clone(), v.clone())));
+                // Imports are added to the locale along with the file, so
+                // the other files in it can use them too.
+                let mut open = false;
+                for (import, pos) in imports {
+                    let imported = context::relative_to(&path, &import);
+                    match context::load(&FileLoader, &imported, &mut vec![path.clone()]) {
+                        Ok(env) => merged.extend(env.into_iter()),
+                        Err(e) => {
+                            errors.push(CheckError {
+                                kind: CheckErrorKind::Import(e),
+                                line: pos.line,
+                                col: pos.col,
+                            });
+                            open = true;
+                        }
+                    }
+                }
+                parsed.push((path, env, open, errors));
+            }
+            for (path, env, open, mut errors) in parsed {
+                errors.extend(self.check_env(&env, &merged, open));
+                if !errors.is_empty() {
+                    sort(&mut errors);
+                    results.insert(path, errors);
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// Checks the resources at `path` like `check_path`, for use in a build
+    /// script. Cargo is told to rerun the build script when they change, and
+    /// any problems are printed and fail the build.
+    pub fn run<P: AsRef<Path>>(&self, path: P) {
+        let path = path.as_ref();
+        println!("cargo:rerun-if-changed={}", path.display());
+        let mut files = BTreeMap::new();
+        if let Err(e) = collect(path, &mut files) {
+            panic!("could not read L20n resources at {}: {}", path.display(), e);
+        }
+        for file in files.values().flat_map(|files| files.iter()) {
+            println!("cargo:rerun-if-changed={}", file.display());
+        }
+
+        let results = match self.check_path(path) {
+            Ok(results) => results,
+            Err(e) => panic!("could not read L20n resources at {}: {}", path.display(), e)
+        };
+        if results.is_empty() {
+            return;
+        }
+        let mut count = 0;
+        for (file, errors) in &results {
+            for error in errors {
+                println!("cargo:warning={}:{}:{}: {}", file.display(), error.line, error.col, error);
+                count += 1;
+            }
+        }
+        panic!("found {} problem(s) in L20n resources at {}", count, path.display());
+    }
+
+    // If the scope is `open`, an id that isn't in it may come from an
+    // import that couldn't be followed, so it isn't reported.
+    fn check_env(&self, env: &compiler::Env, scope: &compiler::Env, open: bool) -> Vec<CheckError> {
+        let mut visitor = CheckVisitor {
+            functions: &self.functions,
+            scope: scope,
+            open: open,
+            errors: vec![],
+        };
+        for entry in env.values() {
+            visitor.visit_entry(entry);
+        }
+        visitor.errors
+    }
+}
+
+struct CheckVisitor<'a> {
+    functions: &'a HashMap<String, Option<usize>>,
+    scope: &'a compiler::Env,
+    open: bool,
+    errors: Vec<CheckError>,
+}
+
+impl<'a> CheckVisitor<'a> {
+    fn error(&mut self, expr: &ast::Expr, kind: CheckErrorKind) {
+        self.errors.push(CheckError {
+            kind: kind,
+            line: expr.span.start.line,
+            col: expr.span.start.col,
+        });
+    }
+}
+
+impl<'a> Visitor for CheckVisitor<'a> {
+    fn visit_expr(&mut self, expr: &ast::Expr) {
+        match expr.kind {
+            ast::AttrExpr(ref parent, ref attr, ast::Static) => {
+                if let (&ast::IdentExpr(ref id), &ast::IdentExpr(ref attr)) = (&parent.kind, &attr.kind) {
+                    if let Some(&ast::Entry { kind: ast::Entity(_, _, _, ref attrs), .. }) = self.scope.get(id) {
+                        if !attrs.iter().any(|a| a.0 == *attr) {
+                            self.error(expr, CheckErrorKind::UndefinedAttr(id.clone(), attr.clone()));
+                        }
+                    }
+                }
+            }
+            ast::CallExpr(ref callee, ref args) => {
+                let name = match callee.kind {
+                    ast::IdentExpr(ref name) => name,
+                    _ => return ast::walk_expr(self, expr)
+                };
+                for arg in args {
+                    self.visit_expr(arg);
+                }
+                let kind = match self.scope.get(name) {
+                    Some(&ast::Entry { kind: ast::Macro(_, ref params, _), .. }) => {
+                        if params.len() == args.len() {
+                            return;
+                        }
+                        CheckErrorKind::WrongNumberOfArgs(name.clone(), params.len(), args.len())
+                    }
+                    Some(_) => CheckErrorKind::NotAMacro(name.clone()),
+                    None => match self.functions.get(name) {
+                        Some(&Some(max)) if args.is_empty() || args.len() > max => {
+                            CheckErrorKind::WrongNumberOfArgs(name.clone(), max, args.len())
+                        }
+                        Some(_) => return,
+                        None if self.open => return,
+                        None => CheckErrorKind::UndefinedMacro(name.clone())
+                    }
+                };
+                return self.error(expr, kind);
+            }
+            ast::IdentExpr(ref id) => {
+                if !self.scope.contains_key(id) && !self.open {
+                    self.error(expr, CheckErrorKind::UndefinedIdent(id.clone()));
+                }
+            }
+            _ => {}
+        }
+        ast::walk_expr(self, expr);
+    }
+}
+
+/// Checks the resources at `path` with the built-in macros, failing the
+/// build if there are problems. See `Checker::run`.
+pub fn check<P: AsRef<Path>>(path: P) {
+    Checker::new().run(path)
+}
+
+/// Generates Rust source with a typed accessor for each public entity in
+/// an L20n resource, so that ids and arguments are checked by the compiler.
+///
+/// An entity that uses variables gets a struct holding them, named after
+/// the entity:
+///
+/// ```text
+/// <hello 'Hello, {{ $userName }}!'>
+/// ```
+///
+/// becomes
+///
+/// ```rust,ignore
+/// pub struct HelloArgs {
+///     pub user_name: String,
+/// }
+///
+/// pub fn hello(locale: &::l20n::Locale, args: HelloArgs) -> ::l20n::LocalizeResult<String>
+/// ```
+///
+/// Variables used by the entities and macros an entity references are
+/// included as well.
+///
+/// The type of each field follows from how the variable is used. One that
+/// is used in arithmetic, compared to a number or passed to `plural`,
+/// `ordinal` or `number` is an `f64`. One that is only interpolated,
+/// compared to a string or used as a key is a `String`. Any other variable,
+/// such as one whose properties are used, or one used both ways, is a
+/// `::l20n::Data`.
+///
+/// Names that are Rust keywords get a trailing `_`. Entities or variables
+/// whose names would be the same in Rust, such as `brandName` and
+/// `brand_name`, are an error.
+pub fn generate(source: &str) -> Result<String, GenerateError> {
+    let env = try!(compiler::compile(source).map_err(GenerateError::Parse));
+    let mut ids: Vec<&String> = env.iter().filter_map(|(id, entry)| match entry.kind {
+        ast::Entity(..) if !id.starts_with('_') => Some(id),
+        _ => None
+    }).collect();
+    ids.sort();
+
+    let mut fns = HashMap::new();
+    let mut structs = HashMap::new();
+    for id in &ids {
+        try!(claim(&mut fns, snake_case(id), id));
+        let vars = compiler::vars(&env, id);
+        if !vars.is_empty() {
+            try!(claim(&mut structs, camel_case(id), id));
+        }
+        let mut fields = HashMap::new();
+        for var in vars {
+            try!(claim(&mut fields, snake_case(&var), &format!("${}", var)));
+        }
+    }
+
+    let mut out = String::from("// Generated from an L20n resource by l20n::build. Do not edit.\n");
+    for id in ids {
+        let vars = compiler::vars(&env, id);
+        let types = types(&env, id);
+        let name = snake_case(id);
+        out.push('\n');
+        if vars.is_empty() {
+            out.push_str(&format!("/// Formats `{}`.\n", id));
+            out.push_str(&format!("pub fn {}(locale: &::l20n::Locale) -> ::l20n::LocalizeResult<String> {{\n", name));
+            out.push_str(&format!("    locale.format({:?}, ())\n", id));
+            out.push_str("}\n");
+            continue;
+        }
+        let args = format!("{}Args", camel_case(id));
+        out.push_str(&format!("/// The variables `{}` uses.\n", id));
+        out.push_str(&format!("pub struct {} {{\n", args));
+        for var in &vars {
+            out.push_str(&format!("    /// `${}`\n", var));
+            out.push_str(&format!("    pub {}: {},\n", snake_case(var), field_type(types.get(var)).0));
+        }
+        out.push_str("}\n\n");
+        out.push_str(&format!("/// Formats `{}`.\n", id));
+        out.push_str(&format!("pub fn {}(locale: &::l20n::Locale, args: {}) -> ::l20n::LocalizeResult<String> {{\n", name, args));
+        out.push_str("    let mut data = ::std::collections::HashMap::new();\n");
+        for var in &vars {
+            let value = match field_type(types.get(var)).1 {
+                Some(variant) => format!("::l20n::Data::{}(args.{})", variant, snake_case(var)),
+                None => format!("args.{}", snake_case(var))
+            };
+            out.push_str(&format!("    data.insert(String::from({:?}), {});\n", var, value));
+        }
+        out.push_str(&format!("    locale.format({:?}, ::l20n::Data::Map(data))\n", id));
+        out.push_str("}\n");
+    }
+    Ok(out)
+}
+
+/// Generates accessors for the resource at `resource` into the file at
+/// `out`, for use in a build script. Cargo is told to rerun the build
+/// script when the resource changes. Usually `out` is in `OUT_DIR`, and the
+/// crate includes it:
+///
+/// ```rust,ignore
+/// mod messages {
+///     include!(concat!(env!("OUT_DIR"), "/messages.rs"));
+/// }
+/// ```
+pub fn generate_file<P: AsRef<Path>, Q: AsRef<Path>>(resource: P, out: Q) {
+    let resource = resource.as_ref();
+    println!("cargo:rerun-if-changed={}", resource.display());
+    let mut source = String::new();
+    if let Err(e) = fs::File::open(resource).and_then(|mut f| f.read_to_string(&mut source)) {
+        panic!("could not read L20n resource at {}: {}", resource.display(), e);
+    }
+    let code = match generate(&source) {
+        Ok(code) => code,
+        Err(e) => panic!("{}: {}", resource.display(), e)
+    };
+    if let Err(e) = fs::File::create(out.as_ref()).and_then(|mut f| f.write_all(code.as_bytes())) {
+        panic!("could not write {}: {}", out.as_ref().display(), e);
+    }
+}
+
+// How a `$variable` is used, which decides the type of its field.
+#[derive(Clone, Copy, PartialEq)]
+enum Use {
+    // Interpolated, which suits a number too, but is usually text.
+    Text,
+    Str,
+    Num,
+    // As a boolean, a map or a date, which only `Data` holds.
+    Other,
+}
+
+// The Rust type of a field for a variable used in these ways, and the
+// variant of `Data` that holds it.
+fn field_type(uses: Option<&Vec<Use>>) -> (&'static str, Option<&'static str>) {
+    let uses = match uses {
+        Some(uses) => uses,
+        None => return ("::l20n::Data", None)
+    };
+    let (num, text) = (uses.contains(&Use::Num), uses.contains(&Use::Str) || uses.contains(&Use::Text));
+    if uses.contains(&Use::Other) || (num && uses.contains(&Use::Str)) {
+        ("::l20n::Data", None)
+    } else if num {
+        ("f64", Some("Float"))
+    } else if text {
+        ("String", Some("Str"))
+    } else {
+        ("::l20n::Data", None)
+    }
+}
+
+// How the entry `id` uses each of the variables `compiler::vars` finds.
+fn types(env: &compiler::Env, id: &str) -> HashMap<String, Vec<Use>> {
+    let mut visitor = TypeVisitor {
+        env: env,
+        uses: HashMap::new(),
+        want: None,
+        seen: HashSet::new(),
+        macros: HashSet::new(),
+    };
+    visitor.seen.insert(id.to_string());
+    if let Some(entry) = env.get(id) {
+        visitor.visit_entry(entry);
+    }
+    visitor.uses
+}
+
+struct TypeVisitor<'a> {
+    env: &'a compiler::Env,
+    uses: HashMap<String, Vec<Use>>,
+    // How the value of the expression being visited is used.
+    want: Option<Use>,
+    seen: HashSet<String>,
+    // The macros being visited, so that recursive calls stop.
+    macros: HashSet<String>,
+}
+
+impl<'a> TypeVisitor<'a> {
+    fn visit_as(&mut self, expr: &ast::Expr, want: Option<Use>) {
+        let outer = mem::replace(&mut self.want, want);
+        self.visit_expr(expr);
+        self.want = outer;
+    }
+
+    // How the macro `name` uses each of its parameters. The variables of
+    // the data it uses are recorded as well.
+    fn params(&mut self, name: &str) -> Vec<Vec<Use>> {
+        let (params, body) = match self.env.get(name) {
+            Some(&ast::Entry { kind: ast::Macro(_, ref params, ref body), .. }) => (params, body),
+            _ => return vec![]
+        };
+        if !self.macros.insert(name.to_string()) {
+            return vec![];
+        }
+        let outer = mem::replace(&mut self.uses, HashMap::new());
+        self.visit_expr(body);
+        let mut uses = mem::replace(&mut self.uses, outer);
+        self.macros.remove(name);
+
+        let params = params.iter().map(|param| match param.kind {
+            ast::VarExpr(ref var) => uses.remove(var).unwrap_or_default(),
+            _ => vec![]
+        }).collect();
+        for (var, used) in uses {
+            self.uses.entry(var).or_insert_with(Vec::new).extend(used);
+        }
+        params
+    }
+}
+
+impl<'a> Visitor for TypeVisitor<'a> {
+    fn visit_entry(&mut self, entry: &ast::Entry) {
+        if let ast::Entity(_, ref value, ref index, ref attrs) = entry.kind {
+            self.visit_value(value);
+            for expr in index {
+                self.visit_as(expr, Some(Use::Str));
+            }
+            for attr in attrs {
+                self.visit_attr(attr);
+            }
+        }
+    }
+
+    fn visit_attr(&mut self, attr: &ast::Attr) {
+        self.visit_value(&attr.1);
+        for expr in &attr.2 {
+            self.visit_as(expr, Some(Use::Str));
+        }
+    }
+
+    fn visit_value(&mut self, value: &ast::Value) {
+        match value.kind {
+            ast::Str(_) => {}
+            ast::ComplexStr(ref exprs) => for expr in exprs {
+                self.visit_as(expr, Some(Use::Text));
+            },
+            ast::Hash(ref map, _, ref index) => {
+                for member in map.values() {
+                    self.visit_value(member);
+                }
+                if let Some(ref expr) = *index {
+                    self.visit_as(expr, Some(Use::Str));
+                }
+            }
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &ast::Expr) {
+        match expr.kind {
+            ast::VarExpr(ref var) => if let Some(want) = self.want {
+                self.uses.entry(var.clone()).or_insert_with(Vec::new).push(want);
+            },
+            ast::IdentExpr(ref id) => if self.seen.insert(id.clone()) {
+                if let Some(entry) = self.env.get(id) {
+                    self.visit_entry(entry);
+                }
+            },
+            ast::BinExpr(ref left, op, ref right) => {
+                let (l, r) = match op {
+                    ast::BiEq | ast::BiNe => (literal_use(right), literal_use(left)),
+                    ast::BiAnd | ast::BiOr => (Some(Use::Other), Some(Use::Other)),
+                    _ => (Some(Use::Num), Some(Use::Num))
+                };
+                self.visit_as(left, l);
+                self.visit_as(right, r);
+            }
+            ast::UnExpr(op, ref operand) => {
+                self.visit_as(operand, Some(if op == ast::UnNot { Use::Other } else { Use::Num }));
+            }
+            ast::CondExpr(ref cond, ref consequent, ref alt) => {
+                self.visit_as(cond, Some(Use::Other));
+                self.visit_expr(consequent);
+                self.visit_expr(alt);
+            }
+            ast::ParenExpr(ref expr) => self.visit_expr(expr),
+            ast::PropExpr(ref parent, ref prop, access) | ast::AttrExpr(ref parent, ref prop, access) => {
+                self.visit_as(parent, Some(Use::Other));
+                if access == ast::Computed {
+                    self.visit_as(prop, Some(Use::Str));
+                }
+            }
+            ast::CallExpr(ref callee, ref args) => {
+                let params = match callee.kind {
+                    ast::IdentExpr(ref name) if self.env.contains_key(name) => self.params(name),
+                    ast::IdentExpr(ref name) => match &name[..] {
+                        "plural" | "ordinal" | "number" => vec![vec![Use::Num]],
+                        "date" | "time" | "datetime" => vec![vec![Use::Other]],
+                        _ => vec![]
+                    },
+                    _ => {
+                        self.visit_as(callee, None);
+                        vec![]
+                    }
+                };
+                for (i, arg) in args.iter().enumerate() {
+                    match params.get(i) {
+                        Some(uses) if !uses.is_empty() => for &want in uses {
+                            self.visit_as(arg, Some(want));
+                        },
+                        _ => self.visit_as(arg, None)
+                    }
+                }
+            }
+            _ => {
+                let outer = self.want.take();
+                ast::walk_expr(self, expr);
+                self.want = outer;
+            }
+        }
+    }
+}
+
+// How a value compared to `other` is used, if `other` is a literal.
+fn literal_use(other: &ast::Expr) -> Option<Use> {
+    match other.kind {
+        ast::NumExpr(_) | ast::FloatExpr(_) => Some(Use::Num),
+        ast::ValExpr(_) => Some(Use::Str),
+        ast::ParenExpr(ref expr) => literal_use(expr),
+        _ => None
+    }
+}
+
+// The strict, reserved and weak keywords of every edition.
+const KEYWORDS: &'static [&'static str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in",
+    "let", "loop", "macro", "macro_rules", "match", "mod", "move", "mut", "override", "priv", "pub",
+    "raw", "ref", "return", "safe", "self", "static", "struct", "super", "trait", "true", "try",
+    "type", "typeof", "union", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+// Records that `name` is generated for `id`, unless another id already has
+// it.
+fn claim(names: &mut HashMap<String, String>, name: String, id: &str) -> Result<(), GenerateError> {
+    if let Some(other) = names.get(&name) {
+        return Err(GenerateError::Collision(name.clone(), other.clone(), id.to_string()));
+    }
+    names.insert(name, id.to_string());
+    Ok(())
+}
+
+// `brandName` becomes `brand_name`, with a trailing `_` if it's a keyword.
+fn snake_case(id: &str) -> String {
+    let mut name = String::new();
+    for (i, c) in id.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 && !name.ends_with('_') {
+                name.push('_');
+            }
+            name.extend(c.to_lowercase());
+        } else {
+            name.push(c);
+        }
+    }
+    if KEYWORDS.contains(&&*name) {
+        name.push('_');
+    }
+    name
+}
+
+// `brand_name` and `brandName` both become `BrandName`.
+fn camel_case(id: &str) -> String {
+    let mut name = String::new();
+    for part in id.split('_') {
+        let mut chars = part.chars();
+        if let Some(c) = chars.next() {
+            name.extend(c.to_uppercase());
+            name.extend(chars);
+        }
+    }
+    name
+}
+
+// Parses a resource, returning its entities and macros, what it imports
+// and where, and its parse errors.
+fn parse(source: &str) -> (compiler::Env, Vec<(String, ast::Pos)>, Vec<CheckError>) {
+    let (entries, errors) = Parser::new(source.chars()).parse_partial();
+    let mut env = compiler::Env::new();
+    let mut imports = vec![];
+    for entry in entries {
+        let id = match entry.kind {
+            ast::Entity(ref id, ..) | ast::Macro(ref id, ..) => id.clone(),
+            ast::Import(ref path) => {
+                imports.push((path.clone(), entry.span.start));
+                continue;
+            }
+            ast::Comment(_) => continue
+        };
+        env.insert(id, entry);
+    }
+    let errors = errors.into_iter().map(|e| CheckError {
+        line: e.line,
+        col: e.col,
+        kind: CheckErrorKind::Parse(e),
+    }).collect();
+    (env, imports, errors)
+}
+
+fn sort(errors: &mut Vec<CheckError>) {
+    errors.sort_by(|a, b| (a.line, a.col).cmp(&(b.line, b.col)));
+}
+
+// Finds the `.l20n` files at `path`, grouped by the directory they're in.
+fn collect(path: &Path, locales: &mut BTreeMap<PathBuf, Vec<PathBuf>>) -> io::Result<()> {
+    if try!(fs::metadata(path)).is_dir() {
+        let mut entries = vec![];
+        for entry in try!(fs::read_dir(path)) {
+            entries.push(try!(entry).path());
+        }
+        entries.sort();
+        for entry in entries {
+            if entry.is_dir() || entry.extension().map_or(false, |ext| ext == "l20n") {
+                try!(collect(&entry, locales));
+            }
+        }
+    } else {
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        locales.entry(dir).or_insert_with(Vec::new).push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::process;
+
+    use super::{generate, Checker, CheckErrorKind, GenerateError};
+
+    fn kinds(checker: &Checker, source: &str) -> Vec<String> {
+        checker.check_resource(source).iter().map(|e| match e.kind {
+            CheckErrorKind::Parse(_) => format!("parse@{}", e.line),
+            ref kind => format!("{:?}@{}:{}", kind, e.line, e.col)
+        }).collect()
+    }
+
+    #[test]
+    fn test_check_resource() {
+        let checker = Checker::new();
+        let src = r#"<brand 'Rust' short: 'R'>
+<hi 'Hi {{ brnad }} {{ brand::long }} {{ brand::short }} {{ $user.name }}'>
+<add($a, $b) { $a + $b }>
+<sum '{{ add(1) }} {{ sub(1, 2) }} {{ brand(1) }} {{ plural($n) }} {{ plural() }}'>
+<broken 'oops' :>
+<fine '{{ ~ }} {{ @hour }} {{ add(1, 2) }}'>"#;
+        assert_eq!(kinds(&checker, src), vec![
+            "UndefinedIdent(\"brnad\")@2:12",
+            "UndefinedAttr(\"brand\", \"long\")@2:24",
+            "WrongNumberOfArgs(\"add\", 2, 1)@4:10",
+            "UndefinedMacro(\"sub\")@4:23",
+            "NotAMacro(\"brand\")@4:39",
+            "WrongNumberOfArgs(\"plural\", 1, 0)@4:71",
+            "parse@5",
+        ]);
+    }
+
+    #[test]
+    fn test_imports() {
+        let checker = Checker::new();
+        assert!(kinds(&checker, "import('brand.l20n')\n<hi '{{ brand }} {{ shout(brand) }}'>").is_empty());
+
+        let dir = env::temp_dir().join(format!("l20n-test-check-imports-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let write = |path: &str, source: &str| {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap().write_all(source.as_bytes()).unwrap();
+        };
+        write("shared/brand.l20n", "<brand 'Rust'>");
+        write("en/main.l20n", "import('../shared/brand.l20n')\n<hi '{{ brand }} {{ brnad }}'>");
+        write("en/other.l20n", "<bye '{{ brand }}'>\nimport('missing.l20n')");
+        let results = checker.check_path(dir.join("en")).unwrap();
+        let kinds = results.iter().map(|(path, errors)| {
+            (path.file_name().unwrap().to_str().unwrap(), errors.iter().map(|e| match e.kind {
+                CheckErrorKind::Import(_) => format!("import@{}:{}", e.line, e.col),
+                ref kind => format!("{:?}@{}:{}", kind, e.line, e.col)
+            }).collect::<Vec<_>>())
+        }).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![
+            ("main.l20n", vec![String::from("UndefinedIdent(\"brnad\")@2:21")]),
+            ("other.l20n", vec![String::from("import@2:1")]),
+        ]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_with_function() {
+        let src = "<shout '{{ upper($name) }}'>";
+        assert_eq!(kinds(&Checker::new(), src), vec!["UndefinedMacro(\"upper\")@1:12"]);
+        assert!(kinds(&Checker::new().with_function("upper"), src).is_empty());
+    }
+
+    #[test]
+    fn test_generate() {
+        let src = r#"<brandName 'Rust'>
+<_private 'hidden'>
+<add($a, $b) { $a + $b + $offset }>
+<type '{{ brandName }} {{ $user.name }} has {{ add($count, 1) }}'>"#;
+        assert_eq!(generate(src).unwrap(), r#"// Generated from an L20n resource by l20n::build. Do not edit.
+
+/// Formats `brandName`.
+pub fn brand_name(locale: &::l20n::Locale) -> ::l20n::LocalizeResult<String> {
+    locale.format("brandName", ())
+}
+
+/// The variables `type` uses.
+pub struct TypeArgs {
+    /// `$count`
+    pub count: f64,
+    /// `$offset`
+    pub offset: f64,
+    /// `$user`
+    pub user: ::l20n::Data,
+}
+
+/// Formats `type`.
+pub fn type_(locale: &::l20n::Locale, args: TypeArgs) -> ::l20n::LocalizeResult<String> {
+    let mut data = ::std::collections::HashMap::new();
+    data.insert(String::from("count"), ::l20n::Data::Float(args.count));
+    data.insert(String::from("offset"), ::l20n::Data::Float(args.offset));
+    data.insert(String::from("user"), args.user);
+    locale.format("type", ::l20n::Data::Map(data))
+}
+"#);
+        assert!(generate("<broken").is_err());
+    }
+
+    #[test]
+    fn test_generate_types() {
+        let src = r#"<twice($n) { $n * 2 }>
+<same($x) { $x }>
+<types '{{ $name }} {{ plural($count) }} {{ $kind == "a" }} {{ twice($times) }} {{ same($id) }}
+{{ $user.name }} {{ $amount + 1 }} {{ $amount == "x" }} {{ $on ? 1 : 0 }} {{ date($when) }}'>
+<keyed[$gender] { male: 'm', *other: 'o' }>"#;
+        let out = generate(src).unwrap();
+        for field in &["name: String", "count: f64", "kind: String", "times: f64", "id: String",
+                       "user: ::l20n::Data", "amount: ::l20n::Data", "on: ::l20n::Data",
+                       "when: ::l20n::Data", "gender: String"] {
+            assert!(out.contains(&format!("    pub {},\n", field)), "no field {}", field);
+        }
+        assert!(out.contains("data.insert(String::from(\"count\"), ::l20n::Data::Float(args.count));"));
+        assert!(out.contains("data.insert(String::from(\"name\"), ::l20n::Data::Str(args.name));"));
+        assert!(out.contains("data.insert(String::from(\"user\"), args.user);"));
+    }
+
+    #[test]
+    fn test_generate_names() {
+        let out = generate("<try 'x'>\n<union '{{ $async }}'>\n<dyn 'z'>").unwrap();
+        assert!(out.contains("pub fn try_(locale"));
+        assert!(out.contains("pub fn union_(locale"));
+        assert!(out.contains("pub fn dyn_(locale"));
+        assert!(out.contains("pub async_: String"));
+
+        match generate("<brandName 'a'>\n<brand_name 'b'>") {
+            Err(GenerateError::Collision(name, a, b)) => {
+                assert_eq!((&*name, &*a, &*b), ("brand_name", "brandName", "brand_name"));
+            }
+            other => panic!("expected a collision, got {:?}", other)
+        }
+        match generate("<hi '{{ $userName }} {{ $user_name }}'>") {
+            Err(GenerateError::Collision(name, ..)) => assert_eq!(name, "user_name"),
+            other => panic!("expected a collision, got {:?}", other)
+        }
+    }
+}
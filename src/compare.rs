@@ -0,0 +1,241 @@
+//! Comparing a translation against the reference resource it was
+//! translated from, to find what's missing or out of date.
+//!
+//! ```rust
+//! let reference = "<hi 'Hi, {{ $name }}' title: 'Greeting'>\n<bye 'Bye'>";
+//! let translation = "<hi 'Hallo'>\n<old 'Alt'>";
+//! let diffs = l20n::compare::compare(reference, translation).unwrap();
+//! assert_eq!(diffs.iter().map(|d| d.to_string()).collect::<Vec<_>>(), vec![
+//!     "bye: missing from the translation",
+//!     "hi: missing attribute title",
+//!     "hi: doesn't use $name",
+//!     "old: obsolete, not in the reference",
+//! ]);
+//! ```
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+
+use ast;
+use compiler::{self, Env};
+use context::{self, FileLoader, LoadError};
+use parser::ParseError;
+
+/// A way a translation differs from its reference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    /// The id of the entity that differs.
+    pub id: String,
+    /// How it differs.
+    pub kind: DifferenceKind,
+    /// Where the difference is: in the reference for what the translation
+    /// is missing, and in the translation otherwise.
+    pub span: ast::Span,
+}
+
+/// The ways a translation can differ from its reference.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DifferenceKind {
+    /// The reference has an entity that the translation doesn't.
+    MissingEntity,
+    /// The translation has an entity that the reference doesn't.
+    ObsoleteEntity,
+    /// The reference entity has an attribute that the translation doesn't.
+    /// Contains the attribute.
+    MissingAttr(String),
+    /// A Hash has different keys than in the reference. Contains the keys
+    /// the translation is missing, and those it has in addition.
+    HashKeys(Vec<String>, Vec<String>),
+    /// The reference uses a variable that the translation doesn't.
+    /// Contains the variable.
+    MissingVar(String),
+    /// The reference defines a macro where the translation has an entity.
+    MacroInReference,
+    /// The translation defines a macro where the reference has an entity.
+    MacroInTranslation,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            DifferenceKind::MissingEntity => write!(f, "{}: missing from the translation", self.id),
+            DifferenceKind::ObsoleteEntity => write!(f, "{}: obsolete, not in the reference", self.id),
+            DifferenceKind::MissingAttr(ref attr) => write!(f, "{}: missing attribute {}", self.id, attr),
+            DifferenceKind::HashKeys(ref missing, ref extra) => {
+                try!(write!(f, "{}: hash keys differ", self.id));
+                if !missing.is_empty() {
+                    try!(write!(f, ", missing {}", missing.join(", ")));
+                }
+                if !extra.is_empty() {
+                    try!(write!(f, ", not in the reference {}", extra.join(", ")));
+                }
+                Ok(())
+            }
+            DifferenceKind::MissingVar(ref var) => write!(f, "{}: doesn't use ${}", self.id, var),
+            DifferenceKind::MacroInReference => write!(f, "{}: a macro in the reference, not an entity", self.id),
+            DifferenceKind::MacroInTranslation => write!(f, "{}: a macro in the translation, not an entity", self.id),
+        }
+    }
+}
+
+/// Compares the public entities of a translation against its reference,
+/// returning the differences ordered by entity id.
+///
+/// Hashes indexed with `plural` or `ordinal` aren't compared, since the
+/// plural categories depend on the language. Imports are skipped, since
+/// there's no file to find them from; use `compare_files` to follow them.
+pub fn compare(reference: &str, translation: &str) -> Result<Vec<Difference>, ParseError> {
+    let reference = try!(compiler::compile(reference));
+    let translation = try!(compiler::compile(translation));
+    Ok(compare_envs(&reference, &translation))
+}
+
+/// Compares the resources in two files like `compare`, along with the
+/// resources they import. A ParseError carries the path of the file that
+/// failed. The spans of imported entities point into the files they were
+/// imported from.
+pub fn compare_files<P: AsRef<Path>, Q: AsRef<Path>>(reference: P, translation: Q) -> Result<Vec<Difference>, LoadError> {
+    let reference = try!(context::load(&FileLoader, reference.as_ref(), &mut vec![]));
+    let translation = try!(context::load(&FileLoader, translation.as_ref(), &mut vec![]));
+    Ok(compare_envs(&reference, &translation))
+}
+
+/// Compares compiled resources, or the merged resources of two locales.
+pub fn compare_envs(reference: &Env, translation: &Env) -> Vec<Difference> {
+    let public = |env: &Env| env.iter().filter_map(|(id, entry)| match entry.kind {
+        ast::Entity(..) if !id.starts_with('_') => Some(id.clone()),
+        _ => None
+    }).collect::<BTreeSet<_>>();
+    let ids = public(reference).union(&public(translation)).cloned().collect::<BTreeSet<_>>();
+
+    let mut diffs = vec![];
+    for id in ids {
+        let diff = |kind, span| Difference {
+            id: id.clone(),
+            kind: kind,
+            span: span,
+        };
+        let (reference_entry, entry) = match (reference.get(&id), translation.get(&id)) {
+            (Some(r), Some(t)) => (r, t),
+            (Some(r), None) => {
+                diffs.push(diff(DifferenceKind::MissingEntity, r.span));
+                continue;
+            }
+            (None, Some(t)) => {
+                diffs.push(diff(DifferenceKind::ObsoleteEntity, t.span));
+                continue;
+            }
+            (None, None) => continue
+        };
+        let (reference_value, reference_attrs, value, attrs) = match (&reference_entry.kind, &entry.kind) {
+            (&ast::Entity(_, ref rv, _, ref ra), &ast::Entity(_, ref tv, _, ref ta)) => (rv, ra, tv, ta),
+            (&ast::Macro(..), _) => {
+                diffs.push(diff(DifferenceKind::MacroInReference, entry.span));
+                continue;
+            }
+            (_, &ast::Macro(..)) => {
+                diffs.push(diff(DifferenceKind::MacroInTranslation, entry.span));
+                continue;
+            }
+            _ => continue
+        };
+
+        for attr in reference_attrs {
+            match attrs.iter().find(|a| a.0 == attr.0) {
+                Some(translated) => {
+                    for (missing, extra, span) in hash_keys(&attr.1, &translated.1) {
+                        diffs.push(diff(DifferenceKind::HashKeys(missing, extra), span));
+                    }
+                }
+                None => diffs.push(diff(DifferenceKind::MissingAttr(attr.0.clone()), attr.3)),
+            }
+        }
+        for (missing, extra, span) in hash_keys(reference_value, value) {
+            diffs.push(diff(DifferenceKind::HashKeys(missing, extra), span));
+        }
+
+        let vars = compiler::vars(translation, &id);
+        for var in compiler::vars(reference, &id) {
+            if !vars.contains(&var) {
+                diffs.push(diff(DifferenceKind::MissingVar(var), entry.span));
+            }
+        }
+    }
+    diffs
+}
+
+// The keys missing from and added to each Hash in `value`, compared with
+// the same Hash in `reference`.
+fn hash_keys(reference: &ast::Value, value: &ast::Value) -> Vec<(Vec<String>, Vec<String>, ast::Span)> {
+    let mut diffs = vec![];
+    let (reference_map, map, index) = match (&reference.kind, &value.kind) {
+        (&ast::Hash(ref r, _, _), &ast::Hash(ref t, _, ref index)) => (r, t, index),
+        _ => return diffs
+    };
+    let plural = match *index {
+        Some(ref index) => match index.kind {
+            ast::CallExpr(ref callee, _) => match callee.kind {
+                ast::IdentExpr(ref name) => name == "plural" || name == "ordinal",
+                _ => false
+            },
+            _ => false
+        },
+        None => false
+    };
+    if !plural {
+        let mut missing = reference_map.keys().filter(|k| !map.contains_key(*k)).cloned().collect::<Vec<_>>();
+        let mut extra = map.keys().filter(|k| !reference_map.contains_key(*k)).cloned().collect::<Vec<_>>();
+        if !missing.is_empty() || !extra.is_empty() {
+            missing.sort();
+            extra.sort();
+            diffs.push((missing, extra, value.span));
+        }
+    }
+    let mut keys = reference_map.keys().filter(|k| map.contains_key(*k)).collect::<Vec<_>>();
+    keys.sort();
+    for key in keys {
+        diffs.extend(hash_keys(&reference_map[key], &map[key]));
+    }
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, DifferenceKind};
+
+    #[test]
+    fn test_compare() {
+        let reference = r#"
+        <_brand 'Rust'>
+        <about 'About {{ _brand }}'>
+        <unread[plural($n)] { one: 'One message', *other: '{{ $n }} messages' }>
+        <gender[$g] { masculine: 'He', feminine: 'She', *neuter: 'They' } title: { short: 'S', long: 'L' }>
+        <hi 'Hi, {{ $name }}' accesskey: 'H'>
+        <shout($s) { $s }>
+        <whisper 'psst'>
+        "#;
+        let translation = r#"
+        <_marke 'Rust'>
+        <about 'Über {{ _marke }}'>
+        <unread[plural($n)] { one: 'Eine Nachricht', few: '{{ $n }}', *other: '{{ $n }} Nachrichten' }>
+        <gender[$g] { masculine: 'Er', weiblich: 'Sie', *neuter: 'Es' } title: { short: 'K' }>
+        <hi 'Hallo'>
+        <old 'Alt'>
+        <shout 'Laut'>
+        <whisper($s) { $s }>
+        "#;
+        let diffs = compare(reference, translation).unwrap().into_iter()
+            .map(|d| (d.id, d.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(diffs, vec![
+            (String::from("gender"), DifferenceKind::HashKeys(vec![String::from("long")], vec![])),
+            (String::from("gender"), DifferenceKind::HashKeys(vec![String::from("feminine")], vec![String::from("weiblich")])),
+            (String::from("hi"), DifferenceKind::MissingAttr(String::from("accesskey"))),
+            (String::from("hi"), DifferenceKind::MissingVar(String::from("name"))),
+            (String::from("old"), DifferenceKind::ObsoleteEntity),
+            (String::from("shout"), DifferenceKind::MacroInReference),
+            (String::from("whisper"), DifferenceKind::MacroInTranslation),
+        ]);
+    }
+}
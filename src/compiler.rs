@@ -1,30 +1,49 @@
 
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::env;
 use std::error;
 use std::error::Error as _StdError;
 use std::fmt;
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use ast::{self, Visitor};
 use data;
+use datetime::DateFormat;
+use number::NumberFormat;
 use parser::{ParseError, Parser};
 use parser;
 
-use self::ResolveError::*;
+pub use self::ResolveErrorKind::*;
 use self::ResolveTarget::*;
 
 
 pub fn compile(source: &str) -> Result<HashMap<String, parser::Entry>, ParseError> {
     let p = Parser::new(source.chars());
     let entries = try!(p.parse());
+    Ok(build_env(entries))
+}
+
+/// Compiles every entry that parses, skipping the malformed ones and
+/// returning their errors alongside.
+pub fn compile_partial(source: &str) -> (HashMap<String, parser::Entry>, Vec<ParseError>) {
+    let p = Parser::new(source.chars());
+    let (entries, errors) = p.parse_partial();
+    (build_env(entries), errors)
+}
 
+/// Compiles entries that have already been parsed.
+pub fn build_env(entries: Vec<parser::Entry>) -> Env {
     let mut map = HashMap::new();
 
     for mut entry in entries {
-        let id = match entry {
-            parser::Comment(..) => continue,
+        let id = match entry.kind {
+            parser::Comment(..) | parser::Import(..) => continue,
             parser::Macro(ref id, _, _) => id.clone(),
             parser::Entity(ref id, ref mut value, ref indices, ref mut attrs)    => {
                 // while we're here, fix up and Hash values with default indices
-                match *value {
+                match value.kind {
                     parser::Hash(..) => {
                         if indices.len() > 0 {
                             add_default_indices(value, indices.iter());
@@ -32,8 +51,8 @@ pub fn compile(source: &str) -> Result<HashMap<String, parser::Entry>, ParseErro
                     },
                     _ => {}
                 };
-                for &mut parser::Attr(_, ref mut value, ref indices) in attrs.iter_mut() {
-                    match *value {
+                for &mut parser::Attr(_, ref mut value, ref indices, _) in attrs.iter_mut() {
+                    match value.kind {
                         parser::Hash(..) => {
                             if indices.len() > 0 {
                                 add_default_indices(value, indices.iter());
@@ -49,12 +68,12 @@ pub fn compile(source: &str) -> Result<HashMap<String, parser::Entry>, ParseErro
         map.insert(id, entry);
     }
 
-    Ok(map)
+    map
 }
 
 
 fn add_default_indices<'r, I: Iterator<Item=&'r parser::Expr> + Clone>(value: &mut parser::Value, mut indices: I) {
-    match *value {
+    match value.kind {
         parser::Hash(ref mut map, _, ref mut def_index) => {
             match indices.next() {
                 Some(idx) => {
@@ -72,39 +91,348 @@ fn add_default_indices<'r, I: Iterator<Item=&'r parser::Expr> + Clone>(value: &m
 
 pub type Env = HashMap<String, parser::Entry>;
 
+/// The `$variables` the entry `id` uses from the data, including those used
+/// by the entities and macros it references.
+pub fn vars(env: &Env, id: &str) -> BTreeSet<String> {
+    let mut visitor = VarVisitor {
+        env: env,
+        vars: BTreeSet::new(),
+        seen: HashSet::new(),
+    };
+    visitor.seen.insert(id.to_string());
+    if let Some(entry) = env.get(id) {
+        visitor.visit_entry(entry);
+    }
+    visitor.vars
+}
+
+struct VarVisitor<'a> {
+    env: &'a Env,
+    vars: BTreeSet<String>,
+    seen: HashSet<String>,
+}
+
+impl<'a> Visitor for VarVisitor<'a> {
+    fn visit_entry(&mut self, entry: &parser::Entry) {
+        match entry.kind {
+            // A macro's parameters aren't variables of the data.
+            parser::Macro(_, ref params, ref body) => {
+                let outer = mem::replace(&mut self.vars, BTreeSet::new());
+                self.visit_expr(body);
+                for param in params {
+                    if let parser::VarExpr(ref name) = param.kind {
+                        self.vars.remove(name);
+                    }
+                }
+                let used = mem::replace(&mut self.vars, outer);
+                self.vars.extend(used);
+            }
+            _ => ast::walk_entry(self, entry)
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &parser::Expr) {
+        match expr.kind {
+            parser::VarExpr(ref name) => {
+                self.vars.insert(name.clone());
+            }
+            parser::IdentExpr(ref id) => {
+                if self.seen.insert(id.clone()) {
+                    if let Some(entry) = self.env.get(id) {
+                        self.visit_entry(entry);
+                    }
+                }
+            }
+            _ => ast::walk_expr(self, expr)
+        }
+    }
+}
+
+
+/// A callback that provides the current value of a global.
+pub type GlobalFn = Box<Fn() -> Result<data::Data, ResolveError> + Send + Sync>;
+
+/// The registry of `@globals` available to L20n expressions, such as
+/// `@hour`. Each global is a callback, so its value can change between
+/// resolutions.
+pub struct Globals {
+    map: HashMap<String, GlobalFn>,
+}
+
+impl Globals {
+    /// Creates an empty registry.
+    pub fn new() -> Globals {
+        Globals {
+            map: HashMap::new()
+        }
+    }
+
+    /// Creates a registry with the built-in globals from the L20n spec:
+    /// `@hour`, the current hour (0-23), and `@os`, one of `linux`, `mac`,
+    /// `win`, `android` or `unknown`.
+    ///
+    /// The spec has `@hour` in the user's local time, but the standard
+    /// library can't tell which time zone the host is in, so it starts out
+    /// in UTC. Call `set_utc_offset` with the user's offset to follow the
+    /// spec.
+    pub fn with_builtins() -> Globals {
+        let mut globals = Globals::new();
+        globals.set_utc_offset(0);
+        globals.insert("os", Box::new(|| {
+            Ok(data::Str(String::from(match env::consts::OS {
+                "linux" | "freebsd" | "dragonfly" | "openbsd" | "netbsd" => "linux",
+                "macos" | "ios" => "mac",
+                "windows" => "win",
+                "android" => "android",
+                _ => "unknown"
+            })))
+        }));
+        globals
+    }
+
+    /// Makes `@hour` the current hour at `minutes` east of UTC, replacing
+    /// any previous `@hour` global.
+    pub fn set_utc_offset(&mut self, minutes: i32) {
+        self.insert("hour", Box::new(move || {
+            let secs = match SystemTime::now().duration_since(UNIX_EPOCH) {
+                Ok(d) => d.as_secs() as i64,
+                Err(_) => 0
+            };
+            let mins = secs / 60 + minutes as i64;
+            Ok(data::Num(((mins / 60) % 24 + 24) % 24))
+        }));
+    }
+
+    /// Registers a global, replacing any previous global with that name.
+    pub fn insert<S: Into<String>>(&mut self, name: S, global: GlobalFn) {
+        self.map.insert(name.into(), global);
+    }
+
+    fn get(&self, name: &str) -> Result<data::Data, ResolveError> {
+        match self.map.get(name) {
+            Some(global) => global(),
+            None => Err(ResolveError::unplaced(MissingGlobal))
+        }
+    }
+}
+
+/// A function implemented by the host, which L20n expressions call like a
+/// macro. It receives the resolved arguments.
+pub type NativeFn = Box<Fn(&[data::Data]) -> Result<data::Data, ResolveError> + Send + Sync>;
+
+/// The registry of native functions available to L20n expressions, such as
+/// the `plural` macro. Macros defined in a resource take precedence over
+/// native functions with the same name.
+pub struct Functions {
+    map: HashMap<String, NativeFn>,
+}
+
+impl Functions {
+    /// Creates an empty registry.
+    pub fn new() -> Functions {
+        Functions {
+            map: HashMap::new()
+        }
+    }
+
+    /// Registers a function, replacing any previous function with that name.
+    pub fn insert<S: Into<String>>(&mut self, name: S, f: NativeFn) {
+        self.map.insert(name.into(), f);
+    }
+
+    fn get(&self, name: &str) -> Option<&NativeFn> {
+        self.map.get(name)
+    }
+}
+
+/// An expression resolved while tracing an entity with `Locale::trace`.
+#[derive(Debug, Clone)]
+pub struct TraceStep {
+    /// How many traced expressions enclose this one.
+    pub depth: usize,
+    /// The expression, as L20n source.
+    pub expr: String,
+    /// Where the expression is in its resource.
+    pub span: parser::Span,
+    /// What the expression resolved to, or the error if it failed.
+    pub result: Result<data::Data, String>,
+}
+
+/// Records each expression resolved, in the order they're started.
+pub struct Trace {
+    steps: RefCell<Vec<TraceStep>>,
+    depth: Cell<usize>,
+}
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace {
+            steps: RefCell::new(vec![]),
+            depth: Cell::new(0),
+        }
+    }
+
+    pub fn into_steps(self) -> Vec<TraceStep> {
+        self.steps.into_inner()
+    }
+}
+
+/// How deep entity references and macro calls may nest by default before
+/// resolving fails with `ResolveErrorKind::RecursionLimit`.
+pub const DEFAULT_RECURSION_LIMIT: usize = 32;
+
 pub struct ResolveContext<'a> {
     data: &'a data::Data,
     env: &'a Env,
+    globals: &'a Globals,
+    functions: Option<&'a Functions>,
+    numbers: Option<&'a NumberFormat>,
+    dates: Option<&'a DateFormat>,
+    trace: Option<&'a Trace>,
     locals: Option<&'a data::Data>,
     index: Option<String>,
+    this: Option<&'a parser::Entry>,
+    // The entities and attributes currently being resolved, outermost first.
+    stack: Vec<String>,
+    depth: usize,
+    limit: usize,
 }
 
 impl<'a> ResolveContext<'a> {
-    pub fn new(env: &'a Env, data: &'a data::Data) -> ResolveContext<'a> {
+    pub fn new(env: &'a Env, globals: &'a Globals, data: &'a data::Data) -> ResolveContext<'a> {
         ResolveContext {
             env: env,
             data: data,
+            globals: globals,
+            functions: None,
+            numbers: None,
+            dates: None,
+            trace: None,
             locals: None,
             index: None,
+            this: None,
+            stack: vec![],
+            depth: 0,
+            limit: DEFAULT_RECURSION_LIMIT,
         }
     }
 
-    fn with_locals(&'a self, locals: &'a data::Data) -> ResolveContext<'a> {
-        ResolveContext {
+    /// Sets how deep entity references and macro calls may nest.
+    pub fn with_recursion_limit(mut self, limit: usize) -> ResolveContext<'a> {
+        self.limit = limit;
+        self
+    }
+
+    /// Formats numbers interpolated into strings with `numbers`, instead of
+    /// plainly.
+    pub fn with_number_format(mut self, numbers: &'a NumberFormat) -> ResolveContext<'a> {
+        self.numbers = Some(numbers);
+        self
+    }
+
+    /// Formats dates interpolated into strings with `dates`, instead of as
+    /// RFC 3339.
+    pub fn with_date_format(mut self, dates: &'a DateFormat) -> ResolveContext<'a> {
+        self.dates = Some(dates);
+        self
+    }
+
+    /// Records every expression resolved in `trace`.
+    pub fn with_trace(mut self, trace: &'a Trace) -> ResolveContext<'a> {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Makes the native functions in `functions` callable from expressions.
+    pub fn with_functions(mut self, functions: &'a Functions) -> ResolveContext<'a> {
+        self.functions = Some(functions);
+        self
+    }
+
+    fn with_locals(&'a self, locals: &'a data::Data) -> Result<ResolveContext<'a>, ResolveError> {
+        if self.depth >= self.limit {
+            return Err(RecursionLimit.into());
+        }
+        Ok(ResolveContext {
             env: self.env,
             data: self.data,
+            globals: self.globals,
+            functions: self.functions,
+            numbers: self.numbers,
+            dates: self.dates,
+            trace: self.trace,
             locals: Some(locals),
             index: None,
-        }
+            this: self.this,
+            stack: self.stack.clone(),
+            depth: self.depth + 1,
+            limit: self.limit,
+        })
     }
 
     fn with_index(&'a self, index: Option<String>) -> ResolveContext<'a> {
         ResolveContext {
             env: self.env,
             data: self.data,
+            globals: self.globals,
+            functions: self.functions,
+            numbers: self.numbers,
+            dates: self.dates,
+            trace: self.trace,
             locals: self.locals,
             index: index,
+            this: self.this,
+            stack: self.stack.clone(),
+            depth: self.depth,
+            limit: self.limit,
+        }
+    }
+
+    /// Creates a context for resolving the value or attributes of `entry`,
+    /// which `~` refers to. `key` names what is being resolved, such as
+    /// `brand` or `brand::gender`, and fails with `ResolveErrorKind::Cycle` if
+    /// it is already being resolved further up.
+    pub fn enter(&'a self, entry: &'a parser::Entry, key: String) -> Result<ResolveContext<'a>, ResolveError> {
+        if self.stack.contains(&key) {
+            let mut path = self.stack.clone();
+            path.push(key);
+            return Err(Cycle(path).into());
+        }
+        if self.depth >= self.limit {
+            return Err(RecursionLimit.into());
         }
+        let mut stack = self.stack.clone();
+        stack.push(key);
+        Ok(ResolveContext {
+            env: self.env,
+            data: self.data,
+            globals: self.globals,
+            functions: self.functions,
+            numbers: self.numbers,
+            dates: self.dates,
+            trace: self.trace,
+            locals: self.locals,
+            index: None,
+            this: Some(entry),
+            stack: stack,
+            depth: self.depth + 1,
+            limit: self.limit,
+        })
+    }
+
+    // The id of the entity being resolved, for errors.
+    fn entity_id(&self) -> String {
+        match self.this {
+            Some(&parser::Entry { kind: parser::Entity(ref id, _, _, _), .. }) => id.clone(),
+            _ => String::new()
+        }
+    }
+
+    // An error about `expr` in the entity being resolved. Kept out of
+    // `ExprKind::resolve` so that its stack frame stays small.
+    #[inline(never)]
+    fn error(&self, kind: fn(String, String) -> ResolveErrorKind, expr: &parser::ExprKind) -> ResolveError {
+        kind(self.entity_id(), expr.to_string()).into()
     }
 }
 
@@ -116,48 +444,181 @@ pub enum ResolveTarget {
     Data(data::Data)
 }
 
-/// Errors that can occur when resolving a set of l20n resources into strings.
-/// These errors are cause by problems in the l20n file, or incorrect Data
-/// provided when localizing.
+/// An error occurred resolving a set of l20n resources into strings. These
+/// errors are cause by problems in the l20n file, or incorrect Data provided
+/// when localizing.
 #[derive(Debug)]
-pub enum ResolveError {
-    /// A resource received a value of the wrong type.
-    WrongType,
-    /// A macro was called with the wrong number of arguments.
-    WrongNumberOfArgs,
-    /// Accessed an index of a Hash that does not exist.
-    MissingIndex,
-    /// Accessed an attribute of an entity that does not exist.
-    MissingAttr,
-    /// Tried to use a $var that did not exist in the provided Data.
-    MissingVar(String),
+pub struct ResolveError {
+    /// The kind of error.
+    pub kind: ResolveErrorKind,
+    /// Where the expression that failed is in the resource, if the error
+    /// came from one.
+    pub span: Option<parser::Span>,
+}
+
+impl ResolveError {
+    /// Creates an error that isn't tied to an expression.
+    pub fn new(kind: ResolveErrorKind) -> ResolveError {
+        ResolveError {
+            kind: kind,
+            span: None,
+        }
+    }
+
+    /// Creates an error of a kind that contains the id of the entity being
+    /// resolved and the offending expression, for functions that don't know
+    /// where they were called from. The expression that called them fills
+    /// those in.
+    pub fn unplaced(kind: fn(String, String) -> ResolveErrorKind) -> ResolveError {
+        ResolveError::new(kind(String::new(), String::new()))
+    }
+
+    /// Fills in the entity id and the offending expression of an error made
+    /// with `unplaced`. Other errors are returned as they are.
+    pub fn placed_in(mut self, id: &str, expr: &str) -> ResolveError {
+        if let Some((old_id, old_expr)) = self.kind.context_mut() {
+            if old_expr.is_empty() {
+                *old_id = id.to_string();
+                *old_expr = expr.to_string();
+            }
+        }
+        self
+    }
+
+    // Errors keep the span of the innermost node that failed.
+    fn at(mut self, span: parser::Span) -> ResolveError {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    // Fills in an unplaced error with the innermost expression it passed
+    // through.
+    #[inline(never)]
+    fn within(mut self, expr: &parser::Expr, ctx: &ResolveContext) -> ResolveError {
+        let unplaced = match self.kind.context_mut() {
+            Some((_, text)) => text.is_empty(),
+            None => false
+        };
+        if unplaced {
+            self = self.placed_in(&ctx.entity_id(), &expr.to_string());
+        }
+        self.at(expr.span)
+    }
+}
+
+impl ResolveErrorKind {
+    // The entity id and expression of the kinds that contain them.
+    fn context_mut(&mut self) -> Option<(&mut String, &mut String)> {
+        match *self {
+            WrongType(ref mut id, ref mut expr) |
+            WrongNumberOfArgs(ref mut id, ref mut expr) |
+            MissingIndex(ref mut id, ref mut expr) |
+            MissingAttr(ref mut id, ref mut expr) |
+            MissingVar(ref mut id, ref mut expr) |
+            MissingIdent(ref mut id, ref mut expr) |
+            MissingGlobal(ref mut id, ref mut expr) |
+            InvalidCall(ref mut id, ref mut expr) |
+            InvalidMacro(ref mut id, ref mut expr) |
+            Arithmetic(ref mut id, ref mut expr) => Some((id, expr)),
+            Cycle(_) | RecursionLimit | FunctionFailed(_) => None
+        }
+    }
+}
+
+impl From<ResolveErrorKind> for ResolveError {
+    fn from(kind: ResolveErrorKind) -> ResolveError {
+        ResolveError::new(kind)
+    }
+}
+
+/// The description of the ResolveError that occurred.
+#[derive(Debug)]
+pub enum ResolveErrorKind {
+    /// A resource received a value of the wrong type. Contains the id of
+    /// the entity being resolved, and the offending expression.
+    WrongType(String, String),
+    /// A macro was called with the wrong number of arguments. Contains the
+    /// id of the entity being resolved, and the offending expression.
+    WrongNumberOfArgs(String, String),
+    /// Accessed an index of a Hash that does not exist. Contains the id of
+    /// the entity being resolved, and the offending expression.
+    MissingIndex(String, String),
+    /// Accessed an attribute of an entity that does not exist. Contains the
+    /// id of the entity being resolved, and the offending expression.
+    MissingAttr(String, String),
+    /// Tried to use a $var that did not exist in the provided Data. Contains
+    /// the id of the entity being resolved, and the offending expression.
+    MissingVar(String, String),
     /// A string tried to use another string in the l20n resource that did not
-    /// exist.
-    MissingIdent(String),
+    /// exist. Contains the id of the entity being resolved, and the offending
+    /// expression.
+    MissingIdent(String, String),
+    /// Tried to use an @global that has not been registered. Contains the id
+    /// of the entity being resolved, and the offending expression.
+    MissingGlobal(String, String),
+    /// Tried to call something that is not a macro. Contains the id of the
+    /// entity being resolved, and the offending expression.
+    InvalidCall(String, String),
+    /// A macro was defined with something other than variables as its
+    /// arguments. Contains the id of the entity being resolved, and the
+    /// offending expression.
+    InvalidMacro(String, String),
+    /// Arithmetic overflowed, or divided by zero. Contains the id of the
+    /// entity being resolved, and the offending expression.
+    Arithmetic(String, String),
+    /// Entities reference each other in a cycle. Contains the entities and
+    /// attributes in the cycle, starting and ending with the same one.
+    Cycle(Vec<String>),
+    /// Entity references or macro calls nested deeper than the recursion
+    /// limit.
+    RecursionLimit,
+    /// A function registered by the application failed. Contains its
+    /// message.
+    FunctionFailed(String),
 }
 
 impl error::Error for ResolveError {
     fn description(&self) -> &str {
-        match *self {
-            ResolveError::WrongType => "A resource received a value of the wrong type",
-            ResolveError::WrongNumberOfArgs => "A macro was called with the wrong number of arguments",
-            ResolveError::MissingIndex => "Accessed an index of a Hash that does not exist",
-            ResolveError::MissingAttr => "Accessed an attribute of an entity that does not exist",
-            ResolveError::MissingVar(_) => "Tried to use a variable that did not exist in the provided Data",
-            ResolveError::MissingIdent(_) => "A string tried to use another string in the l20n resource that did not exist",
+        match self.kind {
+            ResolveErrorKind::WrongType(..) => "A resource received a value of the wrong type",
+            ResolveErrorKind::WrongNumberOfArgs(..) => "A macro was called with the wrong number of arguments",
+            ResolveErrorKind::MissingIndex(..) => "Accessed an index of a Hash that does not exist",
+            ResolveErrorKind::MissingAttr(..) => "Accessed an attribute of an entity that does not exist",
+            ResolveErrorKind::MissingVar(..) => "Tried to use a variable that did not exist in the provided Data",
+            ResolveErrorKind::MissingIdent(..) => "A string tried to use another string in the l20n resource that did not exist",
+            ResolveErrorKind::MissingGlobal(..) => "Tried to use a global that has not been registered",
+            ResolveErrorKind::InvalidCall(..) => "Tried to call something that is not a macro",
+            ResolveErrorKind::InvalidMacro(..) => "A macro was defined with arguments that are not variables",
+            ResolveErrorKind::Arithmetic(..) => "Arithmetic overflowed or divided by zero",
+            ResolveErrorKind::Cycle(_) => "Entities reference each other in a cycle",
+            ResolveErrorKind::RecursionLimit => "Entity references or macro calls nested too deeply",
+            ResolveErrorKind::FunctionFailed(_) => "A registered function failed",
         }
     }
 }
 
 impl fmt::Display for ResolveError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            ResolveError::WrongType => write!(f, "{}", self.description()),
-            ResolveError::WrongNumberOfArgs => write!(f, "{}", self.description()),
-            ResolveError::MissingIndex => write!(f, "{}", self.description()),
-            ResolveError::MissingAttr => write!(f, "{}", self.description()),
-            ResolveError::MissingVar(ref val) => write!(f, "{}: {}", self.description(), val),
-            ResolveError::MissingIdent(ref val) => write!(f, "{}: {}", self.description(), val),
+        try!(match self.kind {
+            ResolveErrorKind::WrongType(ref id, ref expr) |
+            ResolveErrorKind::WrongNumberOfArgs(ref id, ref expr) |
+            ResolveErrorKind::MissingIndex(ref id, ref expr) |
+            ResolveErrorKind::MissingAttr(ref id, ref expr) |
+            ResolveErrorKind::MissingVar(ref id, ref expr) |
+            ResolveErrorKind::MissingIdent(ref id, ref expr) |
+            ResolveErrorKind::MissingGlobal(ref id, ref expr) |
+            ResolveErrorKind::InvalidCall(ref id, ref expr) |
+            ResolveErrorKind::InvalidMacro(ref id, ref expr) |
+            ResolveErrorKind::Arithmetic(ref id, ref expr) => write!(f, "{} in {}: {}", self.description(), id, expr),
+            ResolveErrorKind::Cycle(ref path) => write!(f, "{}: {}", self.description(), path.join(" -> ")),
+            ResolveErrorKind::RecursionLimit => write!(f, "{}", self.description()),
+            ResolveErrorKind::FunctionFailed(ref msg) => write!(f, "{}: {}", self.description(), msg),
+        });
+        match self.span {
+            Some(span) => write!(f, " on line {}, column {}", span.start.line, span.start.col),
+            None => Ok(())
         }
     }
 }
@@ -190,10 +651,12 @@ impl Resolve for ResolveTarget {
 }
 
 impl Resolve for parser::Entry {
-    fn resolve(&self, _ctx: &ResolveContext) -> ResolveResult {
-        match *self {
-            parser::Entity(_, ref value, _, _) => {
-                Ok(Value(value.clone()))
+    fn resolve(&self, ctx: &ResolveContext) -> ResolveResult {
+        match self.kind {
+            // Errors that no expression placed, such as a Hash without the index
+            // asked for, are placed in the entity.
+            parser::Entity(ref id, ref value, _, _) => {
+                value.resolve(&try!(ctx.enter(self, id.clone()))).map_err(|e| e.placed_in(id, id))
             }
             _ => Ok(Data(data::Null))
         }
@@ -201,6 +664,12 @@ impl Resolve for parser::Entry {
 }
 
 impl Resolve for parser::Value {
+    fn resolve(&self, ctx: &ResolveContext) -> ResolveResult {
+        self.kind.resolve(ctx).map_err(|e| e.at(self.span))
+    }
+}
+
+impl Resolve for parser::ValueKind {
     fn resolve(&self, ctx: &ResolveContext) -> ResolveResult {
         match *self {
             parser::Str(ref s) => Ok(Data(data::Str(s.clone()))),
@@ -209,24 +678,19 @@ impl Resolve for parser::Value {
                 for expr in exprs.iter() {
                     vec.push(match expr.resolve_data(ctx) {
                         Ok(data::Str(s)) => s,
-                        Ok(data::Num(n)) => format!("{}", n),
-                        Ok(_) => return Err(WrongType),
+                        Ok(d) => try!(placeable_text(d, expr, ctx)),
                         Err(e) => return Err(e)
                     });
                 }
                 Ok(Data(data::Str(vec.concat())))
             }
             parser::Hash(ref map, ref def_key, ref def_index) => {
+                // Members are resolved here rather than returned, so that
+                // they keep the entity that `~` refers to.
+                let member_ctx = ctx.with_index(None);
                 match ctx.index {
                     Some(ref s) => match map.get(s) {
-                            Some(v) => return Ok(Value(v.clone())),
-                            None => {}
-                    },
-                    None => {}
-                };
-                match *def_key {
-                    Some(ref s) => match map.get(s) {
-                            Some(v) => return Ok(Value(v.clone())),
+                            Some(v) => return v.resolve(&member_ctx),
                             None => {}
                     },
                     None => {}
@@ -234,63 +698,68 @@ impl Resolve for parser::Value {
                 match *def_index {
                     Some(ref e) => match e.resolve_data(ctx) {
                             Ok(data::Str(ref s)) => match map.get(s) {
-                                Some(v) => return Ok(Value(v.clone())),
+                                Some(v) => return v.resolve(&member_ctx),
                                 None => {}
                             },
-                            Ok(_) => return Err(WrongType),
+                            Ok(_) => return Err(WrongType(ctx.entity_id(), e.to_string()).into()),
                             Err(e) => return Err(e)
                     },
                     None => {}
                 };
-                Err(MissingIndex)
+                match *def_key {
+                    Some(ref s) => match map.get(s) {
+                            Some(v) => return v.resolve(&member_ctx),
+                            None => {}
+                    },
+                    None => {}
+                };
+                Err(ResolveError::unplaced(MissingIndex))
             }
         }
     }
 }
 
 impl Resolve for parser::Expr {
+    fn resolve(&self, ctx: &ResolveContext) -> ResolveResult {
+        self.kind.resolve(ctx).map_err(|e| e.within(self, ctx))
+    }
+
+    fn resolve_data(&self, ctx: &ResolveContext) -> Result<data::Data, ResolveError> {
+        if let Some(trace) = ctx.trace {
+            return traced(self, trace, ctx);
+        }
+        match self.resolve(ctx) {
+            Ok(Data(d)) => Ok(d),
+            Ok(other) => other.resolve_data(ctx),
+            Err(e) => Err(e)
+        }
+    }
+}
+
+impl Resolve for parser::ExprKind {
     fn resolve(&self, ctx: &ResolveContext) -> ResolveResult {
         match *self {
             parser::ValExpr(ref val) => Ok(Value(val.clone())),
             parser::NumExpr(ref n) => Ok(Data(data::Num(*n))),
+            parser::FloatExpr(ref n) => Ok(Data(data::Float(*n))),
+            parser::ParenExpr(ref expr) => expr.resolve(ctx),
             parser::BinExpr(ref left, ref op, ref right) => {
                 let left = try!(left.resolve_data(ctx));
                 let right = try!(right.resolve_data(ctx));
-                match (*op, left, right) {
-                    // math ops
-                    (parser::BiAdd, data::Num(l), data::Num(r)) => Ok(Data(data::Num(l + r))),
-                    (parser::BiSub, data::Num(l), data::Num(r)) => Ok(Data(data::Num(l - r))),
-                    (parser::BiMul, data::Num(l), data::Num(r)) => Ok(Data(data::Num(l * r))),
-                    (parser::BiDiv, data::Num(l), data::Num(r)) => Ok(Data(data::Num(l / r))),
-                    (parser::BiRem, data::Num(l), data::Num(r)) => Ok(Data(data::Num(l % r))),
-
-                    (parser::BiLt, data::Num(l), data::Num(r)) => Ok(Data(data::Bool(l < r))),
-                    (parser::BiLe, data::Num(l), data::Num(r)) => Ok(Data(data::Bool(l <= r))),
-                    (parser::BiGt, data::Num(l), data::Num(r)) => Ok(Data(data::Bool(l > r))),
-                    (parser::BiGe, data::Num(l), data::Num(r)) => Ok(Data(data::Bool(l >= r))),
-
-                    // logical ops
-                    (parser::BiAnd, data::Bool(l), data::Bool(r)) => Ok(Data(data::Bool(l && r))),
-                    (parser::BiOr, data::Bool(l), data::Bool(r)) => Ok(Data(data::Bool(l || r))),
-
-                    // equality ops. can be Num, Bool, or Str
-                    (parser::BiEq, data::Bool(l), data::Bool(r)) => Ok(Data(data::Bool(l == r))),
-                    (parser::BiEq, data::Str(l), data::Str(r)) => Ok(Data(data::Bool(l == r))),
-                    (parser::BiEq, data::Num(l), data::Num(r)) => Ok(Data(data::Bool(l == r))),
-                    (parser::BiNe, data::Bool(l), data::Bool(r)) => Ok(Data(data::Bool(l == r))),
-                    (parser::BiNe, data::Str(l), data::Str(r)) => Ok(Data(data::Bool(l == r))),
-                    (parser::BiNe, data::Num(l), data::Num(r)) => Ok(Data(data::Bool(l != r))),
-
-                    (_, _, _) => Err(WrongType)
-                }
+                binary(self, *op, left, right, ctx).map(Data)
             }
             parser::UnExpr(ref op, ref expr) => {
                 let expr = try!(expr.resolve_data(ctx));
                 match (*op, expr) {
                     (parser::UnAdd, data::Num(n)) => Ok(Data(data::Num(n))),
-                    (parser::UnSub, data::Num(n)) => Ok(Data(data::Num(-n))),
+                    (parser::UnAdd, data::Float(n)) => Ok(Data(data::Float(n))),
+                    (parser::UnSub, data::Float(n)) => Ok(Data(data::Float(-n))),
+                    (parser::UnSub, data::Num(n)) => match n.checked_neg() {
+                        Some(n) => Ok(Data(data::Num(n))),
+                        None => Err(Arithmetic(ctx.entity_id(), self.to_string()).into())
+                    },
                     (parser::UnNot, data::Bool(b)) => Ok(Data(data::Bool(!b))),
-                    _ => Err(WrongType)
+                    _ => Err(ctx.error(WrongType, self))
                 }
             }
             parser::VarExpr(ref name) => {
@@ -300,13 +769,20 @@ impl Resolve for parser::Expr {
                 };
                 match ctx.data.get(name) {
                     Some(d) => Ok(Data(d.clone())),
-                    None => Err(MissingVar(name.clone()))
+                    None => Err(ctx.error(MissingVar, self))
+                }
+            }
+            parser::GlobalExpr(ref name) => ctx.globals.get(name).map(Data),
+            parser::ThisExpr => {
+                match ctx.this {
+                    Some(e) => Ok(Entry(e.clone())),
+                    None => Err(ctx.error(MissingIdent, self))
                 }
             }
             parser::IdentExpr(ref ident) => {
                 match ctx.env.get(ident) {
                     Some(e) => Ok(Entry(e.clone())),
-                    None => Err(MissingIdent(ident.clone()))
+                    None => Err(ctx.error(MissingIdent, self))
                 }
             }
             parser::CondExpr(ref cond, ref consequent, ref alt) => {
@@ -318,21 +794,21 @@ impl Resolve for parser::Expr {
                             alt.resolve(ctx)
                         }
                     },
-                    _ => Err(WrongType)
+                    _ => Err(ctx.error(WrongType, self))
                 }
             }
             parser::CallExpr(ref ident, ref args) => {
-                match **ident {
+                match ident.kind {
                     parser::IdentExpr(ref ident) => {
                         match ctx.env.get(ident) {
-                            Some(&parser::Macro(_, ref arg_names, ref body)) => {
+                            Some(&parser::Entry { kind: parser::Macro(_, ref arg_names, ref body), .. }) => {
                                 if args.len() == arg_names.len() {
                                     let mut map = HashMap::new();
                                     for (k, v) in arg_names.iter().zip(args.iter()) {
-                                        let name = match k {
-                                            &parser::VarExpr(ref name) => name.clone(),
+                                        let name = match k.kind {
+                                            parser::VarExpr(ref name) => name.clone(),
                                             // not a VarExpr would be the parser going nuts
-                                            _ => unreachable!()
+                                            _ => return Err(InvalidMacro(ctx.entity_id(), k.to_string()).into())
                                         };
                                         let arg = match v.resolve_data(ctx) {
                                             Ok(val) => val,
@@ -341,28 +817,31 @@ impl Resolve for parser::Expr {
                                         map.insert(name, arg);
                                     }
                                     let locals = data::Map(map);
-                                    body.resolve(&ctx.with_locals(&locals))
+                                    body.resolve(&try!(ctx.with_locals(&locals)))
                                 } else {
-                                    Err(WrongNumberOfArgs)
+                                    Err(ctx.error(WrongNumberOfArgs, self))
                                 }
                             }
-                            Some(_) => Err(WrongType),
-                            None => Err(MissingIdent(ident.clone()))
+                            Some(_) => Err(InvalidCall(ctx.entity_id(), self.to_string()).into()),
+                            None => match ctx.functions.and_then(|functions| functions.get(ident)) {
+                                Some(f) => call_native(f, args, ctx),
+                                None => Err(ctx.error(MissingIdent, self))
+                            }
                         }
                     },
-                    _ => panic!("CallExpr may only have IdentExpr in first spot")
+                    _ => Err(InvalidCall(ctx.entity_id(), self.to_string()).into())
                 }
             }
             parser::PropExpr(ref parent, ref prop, ref access) => {
                 let prop = match *access {
                     parser::Computed => match prop.resolve_data(ctx) {
                         Ok(data::Str(s)) => s,
-                        Ok(_) => return Err(WrongType),
+                        Ok(_) => return Err(ctx.error(WrongType, self)),
                         Err(e) => return Err(e)
                     },
-                    parser::Static => match **prop {
+                    parser::Static => match prop.kind {
                         parser::IdentExpr(ref s) => s.clone(),
-                        _ => return Err(WrongType)
+                        _ => return Err(ctx.error(WrongType, self))
                     }
                 };
 
@@ -370,67 +849,442 @@ impl Resolve for parser::Expr {
                     Ok(Data(data::Map(ref m))) => {
                         match m.get(&prop) {
                             Some(d) => Ok(Data(d.clone())),
-                            None => Err(MissingIndex)
+                            None => Err(ctx.error(MissingIndex, self))
                         }
                     },
                     Ok(Entry(ref e)) => {
-                        match e.resolve(ctx) {
-                            Ok(Value(ref v)) => v.resolve(&ctx.with_index(Some(prop))),
-                            Ok(_) => Err(WrongType),
-                            Err(e) => Err(e)
+                        match e.kind {
+                            parser::Entity(ref id, ref v, _, _) => {
+                                let key = format!("{}.{}", id, prop);
+                                v.resolve(&try!(ctx.enter(e, key)).with_index(Some(prop)))
+                            }
+                            _ => Err(ctx.error(WrongType, self))
                         }
                     },
                     Ok(Value(ref v)) => {
                         v.resolve(&ctx.with_index(Some(prop)))
                     },
-                    Ok(_) => Err(WrongType),
+                    Ok(_) => Err(ctx.error(WrongType, self)),
                     Err(e) => Err(e)
                 }
             }
-            parser::Expr::AttrExpr(ref parent, ref prop, ref access) => {
+            parser::ExprKind::AttrExpr(ref parent, ref prop, ref access) => {
                 let prop = match *access {
                     parser::AccessType::Computed => match prop.resolve_data(ctx) {
                         Ok(data::Str(s)) => s,
-                        Ok(_) => return Err(WrongType),
+                        Ok(_) => return Err(ctx.error(WrongType, self)),
                         Err(e) => return Err(e)
                     },
-                    parser::AccessType::Static => match **prop {
-                        parser::Expr::IdentExpr(ref s) => s.clone(),
-                        _ => return Err(WrongType)
+                    parser::AccessType::Static => match prop.kind {
+                        parser::ExprKind::IdentExpr(ref s) => s.clone(),
+                        _ => return Err(ctx.error(WrongType, self))
                     }
                 };
 
                 match parent.resolve(ctx) {
-                    Ok(Entry(parser::Entry::Entity(_, _, _, ref attrs))) => {
-                        for &parser::Attr(ref id, ref value, _) in attrs.iter() {
+                    Ok(Entry(ref e)) => {
+                        let (entity, attrs) = match e.kind {
+                            parser::EntryKind::Entity(ref id, _, _, ref attrs) => (id, attrs),
+                            _ => return Err(ctx.error(WrongType, self))
+                        };
+                        for &parser::Attr(ref id, ref value, _, _) in attrs.iter() {
                             if *id == prop {
-                                return value.resolve(ctx)
+                                let key = format!("{}::{}", entity, id);
+                                return value.resolve(&try!(ctx.enter(e, key)))
                             }
                         }
-                        Err(MissingAttr)
+                        Err(ctx.error(MissingAttr, self))
                     },
-                    Ok(_) => Err(WrongType),
+                    Ok(_) => Err(ctx.error(WrongType, self)),
                     Err(e) => Err(e)
                 }
             }
-            ref e => panic!("{:?} not yet implemented", e)
         }
     }
 }
 
+// Kept out of `ExprKind::resolve`, which recurses, so that its stack frame
+// stays small.
+#[inline(never)]
+fn binary(expr: &parser::ExprKind, op: parser::BinOp, left: data::Data, right: data::Data,
+          ctx: &ResolveContext) -> Result<data::Data, ResolveError> {
+    let arith = |n: Option<i64>| match n {
+        Some(n) => Ok(data::Num(n)),
+        None => Err(Arithmetic(ctx.entity_id(), expr.to_string()).into())
+    };
+    let float = |n: f64| if n.is_finite() {
+        Ok(data::Float(n))
+    } else {
+        Err(Arithmetic(ctx.entity_id(), expr.to_string()).into())
+    };
+    // An integer mixed with a float is promoted.
+    let (left, right) = match (left, right) {
+        (data::Num(l), data::Float(r)) => (data::Float(l as f64), data::Float(r)),
+        (data::Float(l), data::Num(r)) => (data::Float(l), data::Float(r as f64)),
+        operands => operands
+    };
+    match (op, left, right) {
+        // math ops
+        (parser::BiAdd, data::Num(l), data::Num(r)) => arith(l.checked_add(r)),
+        (parser::BiSub, data::Num(l), data::Num(r)) => arith(l.checked_sub(r)),
+        (parser::BiMul, data::Num(l), data::Num(r)) => arith(l.checked_mul(r)),
+        (parser::BiDiv, data::Num(l), data::Num(r)) => arith(l.checked_div(r)),
+        (parser::BiRem, data::Num(l), data::Num(r)) => arith(l.checked_rem(r)),
+        (parser::BiAdd, data::Float(l), data::Float(r)) => float(l + r),
+        (parser::BiSub, data::Float(l), data::Float(r)) => float(l - r),
+        (parser::BiMul, data::Float(l), data::Float(r)) => float(l * r),
+        (parser::BiDiv, data::Float(l), data::Float(r)) => float(l / r),
+        (parser::BiRem, data::Float(l), data::Float(r)) => float(l % r),
+
+        (parser::BiLt, data::Num(l), data::Num(r)) => Ok(data::Bool(l < r)),
+        (parser::BiLe, data::Num(l), data::Num(r)) => Ok(data::Bool(l <= r)),
+        (parser::BiGt, data::Num(l), data::Num(r)) => Ok(data::Bool(l > r)),
+        (parser::BiGe, data::Num(l), data::Num(r)) => Ok(data::Bool(l >= r)),
+        (parser::BiLt, data::Float(l), data::Float(r)) => Ok(data::Bool(l < r)),
+        (parser::BiLe, data::Float(l), data::Float(r)) => Ok(data::Bool(l <= r)),
+        (parser::BiGt, data::Float(l), data::Float(r)) => Ok(data::Bool(l > r)),
+        (parser::BiGe, data::Float(l), data::Float(r)) => Ok(data::Bool(l >= r)),
+
+        // logical ops
+        (parser::BiAnd, data::Bool(l), data::Bool(r)) => Ok(data::Bool(l && r)),
+        (parser::BiOr, data::Bool(l), data::Bool(r)) => Ok(data::Bool(l || r)),
+
+        // equality ops. can be Num, Float, Bool, or Str
+        (parser::BiEq, data::Bool(l), data::Bool(r)) => Ok(data::Bool(l == r)),
+        (parser::BiEq, data::Str(l), data::Str(r)) => Ok(data::Bool(l == r)),
+        (parser::BiEq, data::Num(l), data::Num(r)) => Ok(data::Bool(l == r)),
+        (parser::BiEq, data::Float(l), data::Float(r)) => Ok(data::Bool(l == r)),
+        (parser::BiNe, data::Bool(l), data::Bool(r)) => Ok(data::Bool(l != r)),
+        (parser::BiNe, data::Str(l), data::Str(r)) => Ok(data::Bool(l != r)),
+        (parser::BiNe, data::Num(l), data::Num(r)) => Ok(data::Bool(l != r)),
+        (parser::BiNe, data::Float(l), data::Float(r)) => Ok(data::Bool(l != r)),
+
+        (_, _, _) => Err(WrongType(ctx.entity_id(), expr.to_string()).into())
+    }
+}
+
+// Kept out of `Expr::resolve_data` like `binary`, so tracing costs nothing
+// on the stack when it's off.
+#[inline(never)]
+fn traced(expr: &parser::Expr, trace: &Trace, ctx: &ResolveContext) -> Result<data::Data, ResolveError> {
+    // The literal parts of strings would only be noise.
+    if let Some(s) = expr.as_literal() {
+        return Ok(data::Str(s.clone()));
+    }
+    let depth = trace.depth.get();
+    let index = trace.steps.borrow().len();
+    trace.steps.borrow_mut().push(TraceStep {
+        depth: depth,
+        expr: expr.to_string(),
+        span: expr.span,
+        result: Ok(data::Null),
+    });
+    trace.depth.set(depth + 1);
+    let result = match expr.resolve(ctx) {
+        Ok(Data(d)) => Ok(d),
+        Ok(other) => other.resolve_data(ctx),
+        Err(e) => Err(e)
+    };
+    trace.depth.set(depth);
+    trace.steps.borrow_mut()[index].result = match result {
+        Ok(ref d) => Ok(d.clone()),
+        Err(ref e) => Err(e.to_string())
+    };
+    result
+}
+
+// Kept out of `ValueKind::resolve` like `binary`.
+#[inline(never)]
+fn placeable_text(d: data::Data, expr: &parser::Expr, ctx: &ResolveContext) -> Result<String, ResolveError> {
+    let text = match (ctx.numbers, ctx.dates, &d) {
+        (_, Some(dates), &data::DateTime(ref dt)) => dates.format_default(dt),
+        (_, None, &data::DateTime(ref dt)) => Some(dt.to_string()),
+        (Some(numbers), _, _) => numbers.format_default(&d),
+        (None, _, &data::Num(n)) => Some(format!("{}", n)),
+        (None, _, &data::Float(n)) => Some(format!("{}", n)),
+        (None, _, _) => None
+    };
+    match text {
+        Some(text) => Ok(text),
+        None => Err(WrongType(ctx.entity_id(), expr.to_string()).into())
+    }
+}
+
+// Kept out of `ExprKind::resolve` like `binary`.
+#[inline(never)]
+fn call_native(f: &NativeFn, args: &[parser::Expr], ctx: &ResolveContext) -> ResolveResult {
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args.iter() {
+        values.push(try!(arg.resolve_data(ctx)));
+    }
+    f(&values).map(Data)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{compile, Resolve, ResolveContext};
-    use data::{Str, Null};
+    use std::collections::HashMap;
+
+    use super::{compile, Globals, Resolve, ResolveContext};
+    use super::ResolveError;
+    use super::Functions;
+    use super::ResolveErrorKind::{Arithmetic, Cycle, InvalidCall, MissingVar, RecursionLimit};
+    use super::ResolveErrorKind::{MissingAttr, MissingIdent, MissingIndex, WrongNumberOfArgs, WrongType};
+    use data::{Data, Map, Num, Str, Null};
 
     #[test]
     fn test_compile() {
         let map = compile("<hi 'hello world'>").unwrap();
         let entity = &map["hi"];
         let data = Null;
-        let ctx = ResolveContext::new(&map, &data);
+        let globals = Globals::new();
+        let ctx = ResolveContext::new(&map, &globals, &data);
 
         assert_eq!(entity.resolve_data(&ctx).unwrap(), Str(String::from("hello world")));
 
     }
+
+    #[test]
+    fn test_globals() {
+        let map = compile("<hi 'Good {{ @hour < 12 ? \"morning\" : \"day\" }}, {{ @user }}'>").unwrap();
+        let mut globals = Globals::new();
+        globals.insert("hour", Box::new(|| Ok(Num(9))));
+        globals.insert("user", Box::new(|| Ok(Str(String::from("Ferris")))));
+        let data = Null;
+        let ctx = ResolveContext::new(&map, &globals, &data);
+
+        assert_eq!(map["hi"].resolve_data(&ctx).unwrap(), Str(String::from("Good morning, Ferris")));
+    }
+
+    #[test]
+    fn test_hour_offset() {
+        let mut globals = Globals::with_builtins();
+        let utc = match globals.get("hour").unwrap() {
+            Num(n) => n,
+            other => panic!("@hour is {:?}", other)
+        };
+        globals.set_utc_offset(-120);
+        assert_eq!(globals.get("hour").unwrap(), Num((utc + 22) % 24));
+        globals.set_utc_offset(9 * 60);
+        assert_eq!(globals.get("hour").unwrap(), Num((utc + 9) % 24));
+    }
+
+    #[test]
+    fn test_this() {
+        let map = compile(r#"
+        <brand "Firefox" gender: "masculine">
+        <about "About {{ ~::name }}" name: "{{ brand }} ({{ ~::kind }})" kind: "browser">
+        <pronoun {
+            masculine: "{{ ~::him }}",
+            *feminine: "her"
+        } him: "him">
+        <seeHim "{{ pronoun[brand::gender] }}">
+        "#).unwrap();
+        let globals = Globals::new();
+        let data = Null;
+        let ctx = ResolveContext::new(&map, &globals, &data);
+
+        assert_eq!(map["about"].resolve_data(&ctx).unwrap(), Str(String::from("About Firefox (browser)")));
+        assert_eq!(map["seeHim"].resolve_data(&ctx).unwrap(), Str(String::from("him")));
+    }
+
+    #[test]
+    fn test_hash_precedence() {
+        // A key asked for wins over the entity's index, and the index wins
+        // over the `*` default, so that `<n[plural($n)] { *other: ... }>`
+        // still picks by the index.
+        let map = compile(r#"
+        <h['b'] { *a: "A", b: "B", c: "C" }>
+        <missing['z'] { *a: "A", b: "B" }>
+        <key "{{ h.c }}">
+        <index "{{ h }}">
+        <unknownKey "{{ h.z }}">
+        <default "{{ missing }}">
+        "#).unwrap();
+        let globals = Globals::new();
+        let data = Null;
+        let ctx = ResolveContext::new(&map, &globals, &data);
+
+        assert_eq!(map["key"].resolve_data(&ctx).unwrap(), Str(String::from("C")));
+        assert_eq!(map["index"].resolve_data(&ctx).unwrap(), Str(String::from("B")));
+        assert_eq!(map["unknownKey"].resolve_data(&ctx).unwrap(), Str(String::from("B")));
+        assert_eq!(map["default"].resolve_data(&ctx).unwrap(), Str(String::from("A")));
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        let map = compile(r#"
+        <paren "{{ (1 + 2) * 3 }}">
+        <div "{{ 1 / (2 - 2) }}">
+        <call "{{ paren(1) }}">
+        <nested "{{ 'a'(1) }}">
+        "#).unwrap();
+        let globals = Globals::new();
+        let data = Null;
+        let ctx = ResolveContext::new(&map, &globals, &data);
+
+        assert_eq!(map["paren"].resolve_data(&ctx).unwrap(), Str(String::from("9")));
+        match map["div"].resolve_data(&ctx) {
+            Err(ResolveError { kind: Arithmetic(ref id, ref expr), .. }) => {
+                assert_eq!(id, "div");
+                assert_eq!(expr, "1 / (2 - 2)");
+            }
+            other => panic!("expected Arithmetic, got {:?}", other)
+        }
+        match map["call"].resolve_data(&ctx) {
+            Err(ResolveError { kind: InvalidCall(ref id, ref expr), .. }) => {
+                assert_eq!(id, "call");
+                assert_eq!(expr, "paren(1)");
+            }
+            other => panic!("expected InvalidCall, got {:?}", other)
+        }
+        match map["nested"].resolve_data(&ctx) {
+            Err(ResolveError { kind: InvalidCall(ref id, _), .. }) => assert_eq!(id, "nested"),
+            other => panic!("expected InvalidCall, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_error_context() {
+        let map = compile(r#"
+        <type "{{ 1 + 'a' }}">
+        <cond "{{ 1 ? 'a' : 'b' }}">
+        <add($a, $b) { $a + $b }>
+        <arity "{{ add(1) }}">
+        <attr "{{ type::missing }}">
+        <native "{{ upper(1) }}">
+        <hash { a: "A" }>
+        <index "{{ hash.b }}">
+        <data "{{ $user.age }}">
+        <var "{{ $nope }}">
+        <ident "{{ nope }}">
+        "#).unwrap();
+        let globals = Globals::new();
+        let mut user = HashMap::new();
+        user.insert(String::from("name"), Str(String::from("Ada")));
+        let mut data = HashMap::new();
+        data.insert(String::from("user"), Map(user));
+        let data = Map(data);
+        let mut functions = Functions::new();
+        functions.insert("upper", Box::new(|args: &[Data]| match args.first() {
+            Some(&Str(ref s)) => Ok(Str(s.to_uppercase())),
+            _ => Err(ResolveError::unplaced(WrongType))
+        }));
+        let ctx = ResolveContext::new(&map, &globals, &data).with_functions(&functions);
+
+        let context = |id: &str| match map[id].resolve_data(&ctx) {
+            Err(ResolveError { kind: WrongType(id, expr), .. }) => ("WrongType", id, expr),
+            Err(ResolveError { kind: WrongNumberOfArgs(id, expr), .. }) => ("WrongNumberOfArgs", id, expr),
+            Err(ResolveError { kind: MissingAttr(id, expr), .. }) => ("MissingAttr", id, expr),
+            Err(ResolveError { kind: MissingIndex(id, expr), .. }) => ("MissingIndex", id, expr),
+            Err(ResolveError { kind: MissingVar(id, expr), .. }) => ("MissingVar", id, expr),
+            Err(ResolveError { kind: MissingIdent(id, expr), .. }) => ("MissingIdent", id, expr),
+            other => panic!("expected an error with context, got {:?}", other)
+        };
+        let expected = |kind, id: &str, expr: &str| (kind, id.to_string(), expr.to_string());
+        assert_eq!(context("type"), expected("WrongType", "type", "1 + \"a\""));
+        assert_eq!(context("cond"), expected("WrongType", "cond", "1 ? \"a\" : \"b\""));
+        assert_eq!(context("arity"), expected("WrongNumberOfArgs", "arity", "add(1)"));
+        assert_eq!(context("attr"), expected("MissingAttr", "attr", "type::missing"));
+        assert_eq!(context("native"), expected("WrongType", "native", "upper(1)"));
+        assert_eq!(context("hash"), expected("MissingIndex", "hash", "hash"));
+        assert_eq!(context("index"), expected("MissingIndex", "index", "hash.b"));
+        assert_eq!(context("data"), expected("MissingIndex", "data", "$user.age"));
+        assert_eq!(context("var"), expected("MissingVar", "var", "$nope"));
+        assert_eq!(context("ident"), expected("MissingIdent", "ident", "nope"));
+    }
+
+    #[test]
+    fn test_floats() {
+        let map = compile(r#"
+        <total "{{ 19.99 * 3 }}">
+        <half "{{ 1 / 2.0 }}">
+        <cmp "{{ 0.5 < 1 && 2.0 == 2 ? 'yes' : 'no' }}">
+        <nan "{{ 1.5 % 0 }}">
+        "#).unwrap();
+        let globals = Globals::new();
+        let data = Null;
+        let ctx = ResolveContext::new(&map, &globals, &data);
+
+        assert_eq!(map["total"].resolve_data(&ctx).unwrap(), Str(String::from("59.97")));
+        assert_eq!(map["half"].resolve_data(&ctx).unwrap(), Str(String::from("0.5")));
+        assert_eq!(map["cmp"].resolve_data(&ctx).unwrap(), Str(String::from("yes")));
+        match map["nan"].resolve_data(&ctx) {
+            Err(ResolveError { kind: Arithmetic(..), .. }) => {}
+            other => panic!("expected Arithmetic, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_equality() {
+        let map = compile(r#"
+        <ne "{{ 'a' != 'b' ? 1 : 0 }}{{ 'a' != 'a' ? 1 : 0 }}{{ 1 != 2 ? 1 : 0 }}{{ (1 == 1) != (1 == 2) ? 1 : 0 }}">
+        <eq "{{ 'a' == 'b' ? 1 : 0 }}{{ 'a' == 'a' ? 1 : 0 }}{{ 1 == 2 ? 1 : 0 }}{{ (1 == 1) == (1 == 2) ? 1 : 0 }}">
+        "#).unwrap();
+        let globals = Globals::new();
+        let data = Null;
+        let ctx = ResolveContext::new(&map, &globals, &data);
+
+        assert_eq!(map["ne"].resolve_data(&ctx).unwrap(), Str(String::from("1011")));
+        assert_eq!(map["eq"].resolve_data(&ctx).unwrap(), Str(String::from("0100")));
+    }
+
+    #[test]
+    fn test_error_span() {
+        let map = compile("<a 'x'>\n<b 'Hi {{ $user.name }}'>").unwrap();
+        let globals = Globals::new();
+        let data = Null;
+        let ctx = ResolveContext::new(&map, &globals, &data);
+
+        let err = map["b"].resolve_data(&ctx).unwrap_err();
+        match err.kind {
+            MissingVar(ref id, ref expr) => assert_eq!((&id[..], &expr[..]), ("b", "$user")),
+            ref other => panic!("expected MissingVar, got {:?}", other)
+        }
+        let span = err.span.expect("span");
+        assert_eq!((span.start.line, span.start.col), (2, 11));
+        assert!(format!("{}", err).ends_with("on line 2, column 11"));
+    }
+
+    #[test]
+    fn test_cycles() {
+        let map = compile(r#"
+        <a "{{ b }}">
+        <b "{{ a }}">
+        <self "{{ ~ }}">
+        <attrs "{{ ~::x }}" x: "{{ ~::y }}" y: "{{ ~::x }}">
+        <twice "{{ brand }} and {{ brand }}">
+        <brand "Rust">
+        <fac($n) { $n == 0 ? 1 : $n * fac($n - 1) }>
+        <small "{{ fac(5) }}">
+        <deep "{{ fac(1000) }}">
+        "#).unwrap();
+        let globals = Globals::new();
+        let data = Null;
+        let ctx = ResolveContext::new(&map, &globals, &data);
+
+        match map["a"].resolve_data(&ctx) {
+            Err(ResolveError { kind: Cycle(path), .. }) => assert_eq!(path, vec!["a", "b", "a"]),
+            other => panic!("expected Cycle, got {:?}", other)
+        }
+        match map["self"].resolve_data(&ctx) {
+            Err(ResolveError { kind: Cycle(path), .. }) => assert_eq!(path, vec!["self", "self"]),
+            other => panic!("expected Cycle, got {:?}", other)
+        }
+        match map["attrs"].resolve_data(&ctx) {
+            Err(ResolveError { kind: Cycle(path), .. }) => assert_eq!(path, vec!["attrs", "attrs::x", "attrs::y", "attrs::x"]),
+            other => panic!("expected Cycle, got {:?}", other)
+        }
+        assert_eq!(map["twice"].resolve_data(&ctx).unwrap(), Str(String::from("Rust and Rust")));
+        assert_eq!(map["small"].resolve_data(&ctx).unwrap(), Str(String::from("120")));
+        match map["deep"].resolve_data(&ctx) {
+            Err(ResolveError { kind: RecursionLimit, .. }) => {},
+            other => panic!("expected RecursionLimit, got {:?}", other)
+        }
+
+        let ctx = ResolveContext::new(&map, &globals, &data).with_recursion_limit(3);
+        match map["small"].resolve_data(&ctx) {
+            Err(ResolveError { kind: RecursionLimit, .. }) => {},
+            other => panic!("expected RecursionLimit, got {:?}", other)
+        }
+    }
 }
@@ -1,66 +1,265 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error;
 use std::error::Error as _StdError;
 use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 use serde;
 
-use compiler::{Resolve, ResolveContext};
+use compiler::{Resolve, ResolveContext, TraceStep};
 use compiler;
 use data;
+use datetime;
+use number;
 use parser;
+use plural;
 
 use self::LocalizeError::*;
 
-/*
-/// The current L20n context.
+/// The current L20n context. A Context holds a Locale for each language tag,
+/// and a fallback chain describing the order in which those Locales are
+/// searched when resolving an entity.
 pub struct Context {
     locales: HashMap<String, Locale>,
-    default_locale: String
+    fallbacks: Vec<String>,
+}
+
+/// The strings resolved by a Context, along with the language tag of the
+/// Locale that each string came from.
+#[derive(Debug)]
+pub struct Localized<T> {
+    /// The resolved strings.
+    pub strings: T,
+    /// Maps each entity id to the tag of the Locale that defined it.
+    pub sources: HashMap<String, String>,
 }
 
 impl Context {
     /// Creates a new Context, using `i-default` as the default locale.
     pub fn new() -> Context {
-        Context::with_default(String::from("i-default"))
+        Context::with_default("i-default")
     }
 
     /// Create a new Context with the specified default locale.
     pub fn with_default<S: Into<String>>(locale: S) -> Context {
-        let mut locales = HashMap::new();
-        locales.insert(locale.into(), Locale::new());
-        Context {
-            locales: locales,
-            default_locale: locale
+        Context::with_fallbacks(vec![locale])
+    }
+
+    /// Create a new Context that searches the locales in the order given,
+    /// such as `["de-AT", "de", "en-US"]`. The first locale is the default.
+    pub fn with_fallbacks<S: Into<String>>(chain: Vec<S>) -> Context {
+        let mut ctx = Context {
+            locales: HashMap::new(),
+            fallbacks: vec![],
+        };
+        ctx.set_fallbacks(chain);
+        ctx
+    }
+
+    /// Replaces the fallback chain. Locales named in the chain that don't
+    /// exist yet are created empty.
+    pub fn set_fallbacks<S: Into<String>>(&mut self, chain: Vec<S>) {
+        self.fallbacks = chain.into_iter().map(Into::into).collect();
+        for tag in &self.fallbacks {
+            if !self.locales.contains_key(tag) {
+                self.locales.insert(tag.clone(), Locale::with_tag(tag.as_str()));
+            }
         }
     }
-    pub fn add_resource(&mut self, res: String) -> Result<(), parser::ParseError>{
-        self.add_locale_resource("i-default".to_string(), res) //self.default_locale.clone(), res)
+
+    /// The fallback chain, starting with the default locale.
+    pub fn fallbacks(&self) -> &[String] {
+        &self.fallbacks
     }
 
+    /// Add a L20n string resource to the default locale.
+    pub fn add_resource(&mut self, res: &str) -> Result<(), parser::ParseError> {
+        let name = match self.fallbacks.first() {
+            Some(name) => name.clone(),
+            None => String::from("i-default")
+        };
+        self.add_locale_resource(&name, res)
+    }
+
+    /// Add a L20n string resource to the locale with the given tag, creating
+    /// the locale if needed.
+    pub fn add_locale_resource(&mut self, name: &str, res: &str) -> Result<(), parser::ParseError> {
+        self.locales.entry(name.to_string()).or_insert_with(|| Locale::with_tag(name)).add_resource(res)
+    }
+
+    /// Add a L20n string resource to the default locale, keeping every
+    /// entity that parses. See `Locale::add_resource_partial`.
+    pub fn add_resource_partial(&mut self, res: &str) -> Vec<parser::ParseError> {
+        let name = match self.fallbacks.first() {
+            Some(name) => name.clone(),
+            None => String::from("i-default")
+        };
+        self.add_locale_resource_partial(&name, res)
+    }
+
+    /// Add a L20n string resource to the locale with the given tag, keeping
+    /// every entity that parses. See `Locale::add_resource_partial`.
+    pub fn add_locale_resource_partial(&mut self, name: &str, res: &str) -> Vec<parser::ParseError> {
+        self.locales.entry(name.to_string()).or_insert_with(|| Locale::with_tag(name)).add_resource_partial(res)
+    }
+
+    /// Add the L20n resource in a file to the default locale.
+    pub fn add_resource_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), LoadError> {
+        let name = match self.fallbacks.first() {
+            Some(name) => name.clone(),
+            None => String::from("i-default")
+        };
+        self.add_locale_resource_file(&name, path)
+    }
+
+    /// Add the L20n resource in a file to the locale with the given tag,
+    /// creating the locale if needed.
+    pub fn add_locale_resource_file<P: AsRef<Path>>(&mut self, name: &str, path: P) -> Result<(), LoadError> {
+        self.locales.entry(name.to_string()).or_insert_with(|| Locale::with_tag(name)).add_resource_file(path)
+    }
 
-    pub fn add_locale_resource(&mut self, name: String, res: String) -> Result<(), parser::ParseError> {
-        let mut locale = self.locales.find_or_insert_with(name, |_| Locale::new());
-        let entities = try!(compiler::compile(res.as_slice()));
-        locale.resources.extend(entities.move_iter());
+    /// Add every resource in a directory laid out as `<dir>/<tag>/*.l20n`,
+    /// such as `locales/en-US/main.l20n`, to the locale named by its
+    /// subdirectory, creating the locales as needed. Files are added in
+    /// order of their names, and loading stops at the first error. Files
+    /// added before the error stay added, so after an error some locales may
+    /// have only part of their resources.
+    pub fn add_resource_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<(), LoadError> {
+        for dir in try!(read_dir(path.as_ref())) {
+            if !dir.is_dir() {
+                continue;
+            }
+            let name = match dir.file_name().and_then(|name| name.to_str()) {
+                Some(name) => String::from(name),
+                None => continue
+            };
+            for file in try!(read_dir(&dir)) {
+                if file.is_file() && file.extension().map_or(false, |ext| ext == "l20n") {
+                    try!(self.add_locale_resource_file(&name, &file));
+                }
+            }
+        }
         Ok(())
     }
 
-    pub fn locale<'a>(&'a self) -> &'a Locale {
-        self.get_locale(self.default_locale.as_slice()).unwrap()
+    /// The default locale, which is the first in the fallback chain.
+    pub fn locale(&self) -> Option<&Locale> {
+        self.fallbacks.first().and_then(|name| self.get_locale(name))
+    }
+
+    /// Gets the locale with the given tag.
+    pub fn get_locale(&self, name: &str) -> Option<&Locale> {
+        self.locales.get(name)
     }
 
-    pub fn get_locale<'a>(&'a self, name: &str) -> Option<&'a Locale> {
-        self.locales.find_equiv(&name)
+    /// Gets the locale with the given tag mutably.
+    pub fn get_locale_mut(&mut self, name: &str) -> Option<&mut Locale> {
+        self.locales.get_mut(name)
     }
 
+    /// Resolves every entity from the first locale in the fallback chain
+    /// that defines it. An entity that fails to resolve, such as one that
+    /// uses a variable the data lacks, comes from the next locale that
+    /// defines it instead, and fails only if it fails in every locale.
+    pub fn localize<T: serde::Deserialize>(&self) -> LocalizeResult<Localized<T>> {
+        self.localize_data_raw(data::Data::Null)
+    }
+
+    /// Same as `localize`, but you provide environment Data for the L20n
+    /// files to use.
+    pub fn localize_data<
+        T: serde::Deserialize,
+        D: serde::Serialize
+        >(&self, data: D) -> LocalizeResult<Localized<T>> {
+        self.localize_data_raw(try!(encode(data)))
+    }
+
+    fn localize_data_raw<T: serde::Deserialize>(&self, data: data::Data) -> LocalizeResult<Localized<T>> {
+        let chain: Vec<(&String, &Locale, Arc<compiler::Env>)> = self.fallbacks.iter()
+            .filter_map(|name| self.locales.get(name).map(|locale| (name, locale, locale.resources())))
+            .collect();
+
+        let mut ids = vec![];
+        let mut seen = HashSet::new();
+        for &(_, _, ref resources) in &chain {
+            for (id, entry) in resources.iter() {
+                if seen.insert(id) && is_public(id, entry) {
+                    ids.push(id.clone());
+                }
+            }
+        }
+
+        // Entities reference each other by id, so references fall back
+        // through the chain as well. An entity that fails to resolve falls
+        // back to the next locale that defines it, which resolves it
+        // against its own entities and those further down the chain.
+        let mut envs: Vec<Option<compiler::Env>> = chain.iter().map(|_| None).collect();
+        let mut map = HashMap::new();
+        let mut sources = HashMap::new();
+        for id in ids {
+            let mut error = None;
+            for (i, &(name, locale, ref resources)) in chain.iter().enumerate() {
+                let entry = match resources.get(&id) {
+                    Some(entry) => entry,
+                    None => continue
+                };
+                if envs[i].is_none() {
+                    envs[i] = Some(chain_env(&chain[i..]));
+                }
+                match locale.resolve_entry(envs[i].as_ref().unwrap(), entry, &data) {
+                    Ok(d) => {
+                        map.insert(id.clone(), d);
+                        sources.insert(id.clone(), name.clone());
+                        error = None;
+                        break;
+                    }
+                    Err(e) => if error.is_none() {
+                        error = Some(e);
+                    }
+                }
+            }
+            if let Some(e) = error {
+                return Err(ResolveError(e));
+            }
+        }
+        Ok(Localized {
+            strings: try!(decode(map)),
+            sources: sources,
+        })
+    }
 }
 
-*/
+// The entities of the locales in a fallback chain, each taking precedence
+// over those of the locales after it.
+fn chain_env(chain: &[(&String, &Locale, Arc<compiler::Env>)]) -> compiler::Env {
+    let mut env = compiler::Env::new();
+    for &(_, _, ref resources) in chain.iter().rev() {
+        env.extend(resources.iter().map(|(id, entry)| (id.clone(), entry.clone())));
+    }
+    env
+}
 
 /// A Locale contains all the resources for a specific language.
+///
+/// The Locale's language tag picks the CLDR plural rules behind the
+/// built-in `plural($n)` and `ordinal($n)` macros, and how numbers and
+/// dates are formatted, both when interpolated and by the built-in
+/// `number($n)`, `date($d)`, `time($d)` and `datetime($d)` macros.
 pub struct Locale {
-    resources: HashMap<String, parser::Entry>
+    tag: String,
+    // Shared with any Watcher, which swaps in a new map when a file changes.
+    resources: Arc<RwLock<Resources>>,
+    globals: compiler::Globals,
+    functions: compiler::Functions,
+    numbers: number::NumberFormat,
+    dates: datetime::DateFormat,
+    recursion_limit: usize,
 }
 
 /// An enum of the various errors that can occur during localization.
@@ -102,25 +301,251 @@ impl fmt::Display for LocalizeError {
     }
 }
 
+/// Fetches the source of L20n resources by path, both those added with
+/// `Locale::add_resource_with` and those they import. Implement it to load
+/// resources from somewhere other than the file system, such as from
+/// strings built into the binary.
+pub trait Loader {
+    /// Returns the source of the resource at the path.
+    fn load(&self, path: &Path) -> io::Result<String>;
+}
+
+/// A Loader that reads resources from files.
+#[derive(Debug, Clone, Copy)]
+pub struct FileLoader;
+
+impl Loader for FileLoader {
+    fn load(&self, path: &Path) -> io::Result<String> {
+        let mut res = String::new();
+        try!(File::open(path).and_then(|mut f| f.read_to_string(&mut res)));
+        Ok(res)
+    }
+}
+
+/// An error loading a resource from a file.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file or directory couldn't be read.
+    Io(PathBuf, io::Error),
+    /// The resource couldn't be parsed. The ParseError's `path` is the file.
+    Parse(parser::ParseError),
+    /// A resource imported by another couldn't be loaded. Contains the path
+    /// of the importing resource, where the import is in it, and the error.
+    Import(PathBuf, parser::Pos, Box<LoadError>),
+    /// Resources import each other. Contains the path of each resource in
+    /// the cycle, starting and ending with the same one.
+    Cycle(Vec<PathBuf>),
+}
+
+impl error::Error for LoadError {
+    fn description(&self) -> &str {
+        match *self {
+            LoadError::Io(..) => "IO error",
+            LoadError::Parse(_) => "Parse error",
+            LoadError::Import(..) => "Import error",
+            LoadError::Cycle(_) => "Import cycle",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            LoadError::Io(_, ref err) => Some(err),
+            LoadError::Parse(ref err) => Some(err),
+            LoadError::Import(_, _, ref err) => Some(&**err),
+            LoadError::Cycle(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+            LoadError::Parse(ref err) => write!(f, "{}: {}", self.description(), err),
+            LoadError::Import(ref path, pos, ref err) => {
+                write!(f, "{}:{}:{}: {}", path.display(), pos.line, pos.col, err)
+            }
+            LoadError::Cycle(ref paths) => {
+                let paths = paths.iter().map(|p| p.display().to_string()).collect::<Vec<_>>();
+                write!(f, "{}: {}", self.description(), paths.join(" -> "))
+            }
+        }
+    }
+}
+
 /// A Result of trying to localize.
 pub type LocalizeResult<T> = Result<T, LocalizeError>;
 
+/// What to publish in place of an entity that failed to resolve during a
+/// partial localization.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Fallback {
+    /// Use the id of the entity.
+    Id,
+    /// Use the untranslated source of the entity's value, with its
+    /// placeables left as written.
+    Source,
+}
+
+/// The strings resolved by a partial localization, along with the errors of
+/// every entity that failed to resolve.
+#[derive(Debug)]
+pub struct Partial<T> {
+    /// The resolved strings, with fallbacks for the entities that failed.
+    pub strings: T,
+    /// The id of each entity that failed, and why, sorted by id.
+    pub errors: Vec<(String, compiler::ResolveError)>,
+}
+
 impl Locale {
 
-    /// Creates a new empty Locale.
+    /// Creates a new empty Locale, tagged `i-default`. Its plural rules put
+    /// every number in the `other` category, and numbers aren't grouped.
     pub fn new() -> Locale {
+        Locale::with_tag("i-default")
+    }
+
+    /// Creates a new empty Locale for the language tag, such as `en-US`.
+    pub fn with_tag<S: Into<String>>(tag: S) -> Locale {
+        let tag = tag.into();
+        let numbers = number::NumberFormat::new(&tag);
+        let dates = datetime::DateFormat::new(&tag);
+        let mut functions = compiler::Functions::new();
+        plural::register(&mut functions, &tag);
+        number::register(&mut functions, numbers.clone());
+        datetime::register(&mut functions, dates.clone());
         Locale {
-            resources: HashMap::new()
+            tag: tag,
+            resources: Arc::new(RwLock::new(Resources {
+                layers: vec![],
+                merged: Arc::new(compiler::Env::new()),
+            })),
+            globals: compiler::Globals::with_builtins(),
+            functions: functions,
+            numbers: numbers,
+            dates: dates,
+            recursion_limit: compiler::DEFAULT_RECURSION_LIMIT,
         }
     }
 
-    /// Add a L20n string resource, and it will be parsed.
+    /// The language tag of this Locale.
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Whether numbers are formatted with data for this Locale's language.
+    /// Languages without CLDR plural rules get the CLDR root format, such
+    /// as `1,234.5`.
+    pub fn has_number_format(&self) -> bool {
+        !self.numbers.is_fallback()
+    }
+
+    /// Whether dates and times are formatted with data for this Locale's
+    /// language. Languages without CLDR plural rules get the CLDR root
+    /// patterns, such as `2016 M01 2 15:04`.
+    pub fn has_date_format(&self) -> bool {
+        !self.dates.is_fallback()
+    }
+
+    /// Add a L20n string resource, and it will be parsed. Imports are
+    /// skipped, since there's no file to find them from; use
+    /// `add_resource_file` or `add_resource_with` to follow them.
     pub fn add_resource(&mut self, res: &str) -> Result<(), parser::ParseError> {
         let entities = try!(compiler::compile(res));
-        self.resources.extend(entities.into_iter());
+        self.extend(entities);
+        Ok(())
+    }
+
+    /// Add the L20n resource in a file, along with the resources it
+    /// imports. A ParseError carries the path of the file.
+    pub fn add_resource_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), LoadError> {
+        self.add_resource_with(&FileLoader, path)
+    }
+
+    /// Add the L20n resource at a path, along with the resources it
+    /// imports, fetching each from the Loader.
+    ///
+    /// An `import('path')` is relative to the directory of the resource
+    /// that makes it. Imports are added before the entities of the
+    /// importing resource, which take precedence over them.
+    pub fn add_resource_with<L: Loader + ?Sized, P: AsRef<Path>>(&mut self, loader: &L, path: P) -> Result<(), LoadError> {
+        let entities = try!(load(loader, path.as_ref(), &mut vec![]));
+        self.extend(entities);
+        Ok(())
+    }
+
+    /// Add a L20n string resource, keeping every entity that parses even if
+    /// others in the resource are malformed. Returns the errors for the
+    /// entries that were skipped, in the order they appear.
+    pub fn add_resource_partial(&mut self, res: &str) -> Vec<parser::ParseError> {
+        let (entities, errors) = compiler::compile_partial(res);
+        self.extend(entities);
+        errors
+    }
+
+    /// Registers a global with a fixed value, which L20n resources can use
+    /// as `@name`. Globals with the same name, including the built-in
+    /// `@hour` and `@os`, are replaced.
+    pub fn register_global<S: Into<String>, D: serde::Serialize>(&mut self, name: S, value: D) -> LocalizeResult<()> {
+        let value = try!(encode(value));
+        self.globals.insert(name, Box::new(move || Ok(value.clone())));
         Ok(())
     }
 
+    /// Registers a global whose value is computed by `f` each time a
+    /// resource uses it.
+    pub fn register_global_fn<S, F, D>(&mut self, name: S, f: F)
+    where S: Into<String>, F: Fn() -> D + Send + Sync + 'static, D: serde::Serialize {
+        self.globals.insert(name, Box::new(move || {
+            encode(f()).map_err(|_| compiler::ResolveError::unplaced(compiler::WrongType))
+        }));
+    }
+
+    /// Registers a function that resources can call like a macro, such as
+    /// `upper($name)`. It receives the resolved arguments, and replaces any
+    /// function with the same name, including the built-in `plural`,
+    /// `ordinal`, `number`, `date`, `time` and `datetime`. Numbers and dates
+    /// that are interpolated without calling one of those are still
+    /// formatted for the Locale's tag. Macros defined in a resource take
+    /// precedence.
+    ///
+    /// ```rust
+    /// use l20n::{Data, Locale, ResolveErrorKind};
+    ///
+    /// let mut locale = Locale::new();
+    /// locale.register_fn("upper", |args| match args {
+    ///     [Data::Str(s)] => Ok(Data::Str(s.to_uppercase())),
+    ///     _ => Err(ResolveErrorKind::FunctionFailed("upper takes a string".into()).into())
+    /// });
+    /// locale.add_resource("<shout '{{ upper(\"hey\") }}!'>").unwrap();
+    /// assert_eq!(locale.format("shout", ()).unwrap(), "HEY!");
+    /// ```
+    pub fn register_fn<S, F>(&mut self, name: S, f: F)
+    where S: Into<String>, F: Fn(&[data::Data]) -> Result<data::Data, compiler::ResolveError> + Send + Sync + 'static {
+        self.functions.insert(name, Box::new(f));
+    }
+
+    /// Sets the user's time zone for `@hour`, in `minutes` east of UTC.
+    ///
+    /// The L20n spec has `@hour` in local time, but the standard library
+    /// can't tell which time zone the host is in, so `@hour` is in UTC
+    /// until this is called.
+    ///
+    /// ```
+    /// let mut locale = l20n::Locale::new();
+    /// locale.set_utc_offset(5 * 60 + 30);
+    /// ```
+    pub fn set_utc_offset(&mut self, minutes: i32) {
+        self.globals.set_utc_offset(minutes);
+    }
+
+    /// Sets how deeply entity references and macro calls may nest while
+    /// resolving, which defaults to 32. Deeper nesting fails with
+    /// `ResolveError::RecursionLimit` instead of overflowing the stack.
+    pub fn set_recursion_limit(&mut self, limit: usize) {
+        self.recursion_limit = limit;
+    }
+
     /// Resolves all the resouces into Strings, and returns a Deserialize
     /// object of your choosing.
     pub fn localize<T: serde::Deserialize>(&self) -> LocalizeResult<T> {
@@ -133,45 +558,440 @@ impl Locale {
         T: serde::Deserialize,
         D: serde::Serialize
         >(&self, data: D) -> LocalizeResult<T> {
-        let mut enc = data::Encoder::new();
-        match data.serialize(&mut enc) {
-            Err(e) => return Err(EncodeError(e)),
-            _ => {}
+        self.localize_data_raw(try!(encode(data)))
+    }
+
+    /// Resolves all the resources like `localize`, but doesn't stop at the
+    /// first error. Entities that fail to resolve are replaced according to
+    /// `fallback`, and their errors are collected.
+    pub fn localize_partial<T: serde::Deserialize>(&self, fallback: Fallback) -> LocalizeResult<Partial<T>> {
+        self.localize_data_partial_raw(data::Data::Null, fallback)
+    }
+
+    /// Same as `localize_partial`, but you provide environment Data for the
+    /// L20n files to use.
+    pub fn localize_data_partial<
+        T: serde::Deserialize,
+        D: serde::Serialize
+        >(&self, data: D, fallback: Fallback) -> LocalizeResult<Partial<T>> {
+        self.localize_data_partial_raw(try!(encode(data)), fallback)
+    }
+
+    /// Resolves a single entity into a String. Only the entity and the
+    /// entities it references are resolved, so errors elsewhere in the
+    /// resources don't affect it.
+    pub fn format<D: serde::Serialize>(&self, id: &str, data: D) -> LocalizeResult<String> {
+        let data = try!(encode(data));
+        let resources = self.resources();
+        let entry = try!(entity(&resources, id));
+        match self.resolve_entry(&resources, entry, &data).and_then(|d| stringify(d, id)) {
+            Ok(s) => Ok(s),
+            Err(e) => Err(ResolveError(e))
         }
-        self.localize_data_raw(enc.data().unwrap())
+    }
+
+    /// Same as `format`, but resolves an attribute of the entity.
+    pub fn format_attr<D: serde::Serialize>(&self, id: &str, attr: &str, data: D) -> LocalizeResult<String> {
+        let data = try!(encode(data));
+        let resources = self.resources();
+        let entry = try!(entity(&resources, id));
+        let key = format!("{}::{}", id, attr);
+        let value = match entry.kind {
+            parser::Entity(_, _, _, ref attrs) => match attrs.iter().find(|a| a.0 == attr) {
+                Some(&parser::Attr(_, ref value, _, _)) => value,
+                None => return Err(resolve_error(compiler::MissingAttr(id.to_string(), key)))
+            },
+            _ => return Err(resolve_error(compiler::WrongType(id.to_string(), id.to_string())))
+        };
+        let ctx = self.resolve_context(&resources, &data);
+        match ctx.enter(entry, key.clone()).and_then(|ctx| value.resolve_data(&ctx)).and_then(|d| stringify(d, &key)) {
+            Ok(s) => Ok(s),
+            Err(e) => Err(ResolveError(e.placed_in(id, &key)))
+        }
+    }
+
+    /// Same as `format`, but also returns each expression resolved along
+    /// the way, in the order they were started. Steps nested in another,
+    /// such as the arguments of a call, have a greater `depth`.
+    pub fn trace<D: serde::Serialize>(&self, id: &str, data: D) -> (LocalizeResult<String>, Vec<TraceStep>) {
+        let data = match encode(data) {
+            Ok(data) => data,
+            Err(e) => return (Err(e), vec![])
+        };
+        let resources = self.resources();
+        let entry = match entity(&resources, id) {
+            Ok(entry) => entry,
+            Err(e) => return (Err(e), vec![])
+        };
+        let trace = compiler::Trace::new();
+        let result = {
+            let ctx = self.resolve_context(&resources, &data).with_trace(&trace);
+            match entry.resolve_data(&ctx).and_then(|d| stringify(d, id)) {
+                Ok(s) => Ok(s),
+                Err(e) => Err(ResolveError(e))
+            }
+        };
+        (result, trace.into_steps())
     }
 
     fn localize_data_raw<T: serde::Deserialize>(&self, data: data::Data) -> LocalizeResult<T> {
+        let resources = self.resources();
         let mut map = HashMap::new();
-        let ctx = ResolveContext::new(&self.resources, &data);
-        for (id, entry) in &self.resources {
-            // Only publish public entries. Entries that start with an underscore are helpers.
-            if !id.starts_with('_') {
-                match entry {
-                    &parser::Entity(..) => {
-                        map.insert(id.clone(), match entry.resolve_data(&ctx) {
-                            Ok(d) => d,
-                            Err(e) => return Err(ResolveError(e))
-                        });
+        for (id, entry) in resources.iter() {
+            if is_public(id, entry) {
+                map.insert(id.clone(), match self.resolve_entry(&resources, entry, &data) {
+                    Ok(d) => d,
+                    Err(e) => return Err(ResolveError(e))
+                });
+            }
+        }
+        decode(map)
+    }
+
+    fn localize_data_partial_raw<T: serde::Deserialize>(&self, data: data::Data, fallback: Fallback) -> LocalizeResult<Partial<T>> {
+        let mut map = HashMap::new();
+        let mut errors = vec![];
+        let resources = self.resources();
+        for (id, entry) in resources.iter() {
+            if is_public(id, entry) {
+                let d = match self.resolve_entry(&resources, entry, &data) {
+                    Ok(d) => d,
+                    Err(e) => {
+                        errors.push((id.clone(), e));
+                        data::Str(match (fallback, &entry.kind) {
+                            (Fallback::Source, &parser::Entity(_, ref value, _, _)) => source_text(value),
+                            _ => id.clone()
+                        })
                     }
-                    _ => () // dont localize comments or macros
+                };
+                map.insert(id.clone(), d);
+            }
+        }
+        errors.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(Partial {
+            strings: try!(decode(map)),
+            errors: errors,
+        })
+    }
+
+    // The resources as they are now. A Watcher may swap in new ones at any
+    // time, so each localization works with a single snapshot.
+    fn resources(&self) -> Arc<compiler::Env> {
+        self.resources.read().unwrap().merged.clone()
+    }
+
+    fn extend(&mut self, entities: compiler::Env) {
+        self.resources.write().unwrap().push(entities);
+    }
+
+    fn resolve_entry(&self, env: &compiler::Env, entry: &parser::Entry, data: &data::Data) -> Result<data::Data, compiler::ResolveError> {
+        let ctx = self.resolve_context(env, data);
+        entry.resolve_data(&ctx)
+    }
+
+    fn resolve_context<'a>(&'a self, env: &'a compiler::Env, data: &'a data::Data) -> ResolveContext<'a> {
+        ResolveContext::new(env, &self.globals, data)
+            .with_functions(&self.functions)
+            .with_number_format(&self.numbers)
+            .with_date_format(&self.dates)
+            .with_recursion_limit(self.recursion_limit)
+    }
+}
+
+/// Reloads resource files into a Locale when they change, such as while
+/// translators edit them during development.
+///
+/// The Locale's resources are swapped atomically, so a localization in
+/// progress sees either the old resources or the new ones. A file that
+/// fails to load keeps the entities of the last version that loaded.
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// let mut locale = l20n::Locale::with_tag("en-US");
+/// let mut watcher = l20n::Watcher::new(&locale);
+/// watcher.add_file("locales/en-US/main.l20n").unwrap();
+/// watcher.spawn(Duration::from_secs(1), |e| println!("{}", e));
+/// ```
+pub struct Watcher {
+    // Weak, so that the Watcher doesn't keep a dropped Locale's resources
+    // alive.
+    resources: Weak<RwLock<Resources>>,
+    files: Vec<WatchedFile>,
+}
+
+struct WatchedFile {
+    path: PathBuf,
+    // The modification time and length when the file was last read.
+    version: Option<(SystemTime, u64)>,
+    // The index of the file's resources among the Locale's.
+    layer: usize,
+}
+
+// The resources of a Locale in the order they were added, and merged into
+// one map in which the later ones take precedence.
+struct Resources {
+    layers: Vec<compiler::Env>,
+    merged: Arc<compiler::Env>,
+}
+
+impl Resources {
+    // Merges in place unless a localization in progress holds the merged
+    // map, so loading many files doesn't copy it for each one.
+    fn push(&mut self, entities: compiler::Env) -> usize {
+        Arc::make_mut(&mut self.merged).extend(entities.iter().map(|(id, entry)| (id.clone(), entry.clone())));
+        self.layers.push(entities);
+        self.layers.len() - 1
+    }
+
+    // Only the ids in the old or the new version of the layer can change,
+    // each to its definition in the last layer that still has one.
+    fn replace(&mut self, layer: usize, entities: compiler::Env) {
+        let old = mem::replace(&mut self.layers[layer], entities);
+        let merged = Arc::make_mut(&mut self.merged);
+        for id in old.keys().chain(self.layers[layer].keys()) {
+            match self.layers.iter().rev().filter_map(|layer| layer.get(id)).next() {
+                Some(entry) => merged.insert(id.clone(), entry.clone()),
+                None => merged.remove(id)
+            };
+        }
+    }
+}
+
+impl Watcher {
+    /// Creates a Watcher that reloads files into the Locale.
+    pub fn new(locale: &Locale) -> Watcher {
+        Watcher {
+            resources: Arc::downgrade(&locale.resources),
+            files: vec![],
+        }
+    }
+
+    /// Adds the resource in a file to the Locale, like
+    /// `Locale::add_resource_file`, and watches the file for changes. The
+    /// file is watched even if it fails to load. The resources it imports
+    /// are reloaded with it, but aren't watched themselves.
+    ///
+    /// The file keeps its place among the Locale's resources when it is
+    /// reloaded, so resources added after it still take precedence.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), LoadError> {
+        let resources = match self.resources.upgrade() {
+            Some(resources) => resources,
+            None => return Ok(())
+        };
+        let layer = resources.write().unwrap().push(compiler::Env::new());
+        self.files.push(WatchedFile {
+            path: path.as_ref().to_path_buf(),
+            version: None,
+            layer: layer,
+        });
+        let i = self.files.len() - 1;
+        self.reload(i)
+    }
+
+    /// Adds every resource in a directory laid out as `<dir>/<tag>/*.l20n`
+    /// whose subdirectory is the Locale's tag, and watches them.
+    pub fn add_dir<P: AsRef<Path>>(&mut self, path: P, tag: &str) -> Result<(), LoadError> {
+        for file in try!(read_dir(&path.as_ref().join(tag))) {
+            if file.is_file() && file.extension().map_or(false, |ext| ext == "l20n") {
+                try!(self.add_file(file));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reloads every file that changed since it was last read, returning
+    /// the errors of those that failed to load.
+    pub fn poll(&mut self) -> Vec<LoadError> {
+        let mut errors = vec![];
+        for i in 0..self.files.len() {
+            if version(&self.files[i].path) != self.files[i].version {
+                if let Err(e) = self.reload(i) {
+                    errors.push(e);
+                }
+            }
+        }
+        errors
+    }
+
+    /// Polls in a new thread every `interval`, passing each error to
+    /// `on_error`, until the Locale is dropped.
+    pub fn spawn<F>(mut self, interval: Duration, mut on_error: F) -> thread::JoinHandle<()>
+        where F: FnMut(LoadError) + Send + 'static
+    {
+        thread::spawn(move || {
+            while self.resources.upgrade().is_some() {
+                for e in self.poll() {
+                    on_error(e);
                 }
+                thread::sleep(interval);
             }
+        })
+    }
+
+    fn reload(&mut self, i: usize) -> Result<(), LoadError> {
+        let file = &mut self.files[i];
+        // Read the version first, so a change while loading is seen by the
+        // next poll.
+        file.version = version(&file.path);
+        let entities = try!(load(&FileLoader, &file.path, &mut vec![]));
+        if let Some(resources) = self.resources.upgrade() {
+            resources.write().unwrap().replace(file.layer, entities);
+        }
+        Ok(())
+    }
+}
+
+// Only publish public entities. Entries that start with an underscore are
+// helpers, and comments or macros aren't localized.
+fn is_public(id: &str, entry: &parser::Entry) -> bool {
+    match entry.kind {
+        parser::Entity(..) => !id.starts_with('_'),
+        _ => false
+    }
+}
+
+// The text of a value as the translator wrote it, without its quotes.
+fn source_text(value: &parser::Value) -> String {
+    match value.kind {
+        parser::Str(ref s) => s.clone(),
+        parser::ComplexStr(ref exprs) => exprs.iter().map(|expr| match expr.as_literal() {
+            Some(s) => s.clone(),
+            None => format!("{{{{ {} }}}}", expr)
+        }).collect(),
+        parser::Hash(..) => format!("{}", value)
+    }
+}
+
+fn entity<'a>(env: &'a compiler::Env, id: &str) -> LocalizeResult<&'a parser::Entry> {
+    match env.get(id) {
+        Some(entry) => match entry.kind {
+            parser::Entity(..) => Ok(entry),
+            _ => Err(resolve_error(compiler::WrongType(id.to_string(), id.to_string())))
+        },
+        None => Err(resolve_error(compiler::MissingIdent(id.to_string(), id.to_string())))
+    }
+}
+
+// `id` is the entity or attribute that resolved to `data`, for errors.
+fn stringify(data: data::Data, id: &str) -> Result<String, compiler::ResolveError> {
+    match data {
+        data::Str(s) => Ok(s),
+        data::Num(n) => Ok(format!("{}", n)),
+        data::Float(n) => Ok(format!("{}", n)),
+        _ => Err(compiler::ResolveError::new(compiler::WrongType(id.to_string(), id.to_string())))
+    }
+}
+
+fn resolve_error(kind: compiler::ResolveErrorKind) -> LocalizeError {
+    ResolveError(compiler::ResolveError::new(kind))
+}
+
+fn encode<D: serde::Serialize>(data: D) -> LocalizeResult<data::Data> {
+    let mut enc = data::Encoder::new();
+    match data.serialize(&mut enc) {
+        Err(e) => return Err(EncodeError(e)),
+        _ => {}
+    }
+    match enc.data() {
+        Some(d) => Ok(d),
+        None => Err(EncodeError(data::EncodeError::MissingElements))
+    }
+}
+
+fn decode<T: serde::Deserialize>(map: HashMap<String, data::Data>) -> LocalizeResult<T> {
+    let mut dec = data::Decoder::new(data::Data::Map(map));
+    match serde::Deserialize::deserialize(&mut dec) {
+        Err(e) => Err(DecodeError(e)),
+        Ok(t) => Ok(t)
+    }
+}
+
+// Loads and compiles a resource and those it imports. `importing` holds
+// the resources being loaded that led to this one, to catch cycles.
+pub fn load<L: Loader + ?Sized>(loader: &L, path: &Path, importing: &mut Vec<PathBuf>) -> Result<compiler::Env, LoadError> {
+    if let Some(i) = importing.iter().position(|p| p == path) {
+        let mut cycle = importing[i..].to_vec();
+        cycle.push(path.to_path_buf());
+        return Err(LoadError::Cycle(cycle));
+    }
+    let res = match loader.load(path) {
+        Ok(res) => res,
+        Err(e) => return Err(LoadError::Io(path.to_path_buf(), e))
+    };
+    let entries = match parser::Parser::new(res.chars()).parse() {
+        Ok(entries) => entries,
+        Err(mut e) => {
+            e.path = Some(path.to_path_buf());
+            return Err(LoadError::Parse(e));
+        }
+    };
+
+    let mut env = compiler::Env::new();
+    importing.push(path.to_path_buf());
+    for entry in &entries {
+        if let parser::Import(ref import) = entry.kind {
+            let imported = try!(load(loader, &relative_to(path, import), importing).map_err(|e| {
+                LoadError::Import(path.to_path_buf(), entry.span.start, Box::new(e))
+            }));
+            env.extend(imported.into_iter());
         }
-        let mut dec = data::Decoder::new(data::Data::Map(map));
-        match serde::Deserialize::deserialize(&mut dec) {
-            Err(e) => Err(DecodeError(e)),
-            Ok(t) => Ok(t)
+    }
+    importing.pop();
+    env.extend(compiler::build_env(entries).into_iter());
+    Ok(env)
+}
+
+// The path of a resource imported by the one at `base`. `.` and `..` are
+// resolved, so the same resource always has the same path.
+pub fn relative_to(base: &Path, import: &str) -> PathBuf {
+    let mut path = PathBuf::new();
+    for component in base.parent().unwrap_or(Path::new("")).join(import).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match path.components().next_back() {
+                Some(Component::Normal(_)) => { path.pop(); }
+                _ => path.push(".."),
+            },
+            _ => path.push(component.as_os_str()),
         }
     }
+    path
+}
+
+fn version(path: &Path) -> Option<(SystemTime, u64)> {
+    fs::metadata(path).and_then(|meta| meta.modified().map(|time| (time, meta.len()))).ok()
+}
+
+// The entries of a directory, sorted by name.
+fn read_dir(path: &Path) -> Result<Vec<PathBuf>, LoadError> {
+    let io_error = |e| LoadError::Io(path.to_path_buf(), e);
+    let mut paths = vec![];
+    for entry in try!(fs::read_dir(path).map_err(&io_error)) {
+        paths.push(try!(entry.map_err(&io_error)).path());
+    }
+    paths.sort();
+    Ok(paths)
 }
 
 #[cfg(test)]
 mod tests {
 
     use std::collections::HashMap;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::process;
+    use std::sync::Arc;
+    use std::time::Duration;
 
-    use super::Locale;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use super::{Context, Fallback, Locale, LoadError, Loader, Localized, Partial, Resources, Watcher};
+    use super::LocalizeError::ResolveError;
+    use compiler;
+    use data;
 
     /* custom serde impls are hard
     use serde;
@@ -209,4 +1029,422 @@ mod tests {
         assert_eq!(t["mail"], "Email in your inbox: too many.");
     }
 
+    #[test]
+    fn test_context_fallbacks() {
+        let mut ctx = Context::with_fallbacks(vec!["de-AT", "de", "en-US"]);
+        ctx.add_locale_resource("en-US", "<hi 'Hello'> <bye 'Goodbye'> <brand 'Rust'>").unwrap();
+        ctx.add_locale_resource("de", "<hi 'Hallo'> <bye 'Auf Wiedersehen'>").unwrap();
+        ctx.add_locale_resource("de-AT", "<hi 'Servus, {{ brand }}'>").unwrap();
+
+        let t: Localized<HashMap<String, String>> = ctx.localize().unwrap();
+
+        assert_eq!(t.strings["hi"], "Servus, Rust");
+        assert_eq!(t.strings["bye"], "Auf Wiedersehen");
+        assert_eq!(t.strings["brand"], "Rust");
+        assert_eq!(t.sources["hi"], "de-AT");
+        assert_eq!(t.sources["bye"], "de");
+        assert_eq!(t.sources["brand"], "en-US");
+    }
+
+    #[test]
+    fn test_context_fallback_on_error() {
+        let mut ctx = Context::with_fallbacks(vec!["de", "en-US"]);
+        ctx.add_locale_resource("en-US", "<hi 'Hello'> <bye 'Goodbye'>").unwrap();
+        ctx.add_locale_resource("de", "<hi 'Hallo, {{ $user }}'> <bye 'Tschüss, {{ $user }}'>").unwrap();
+
+        let t: Localized<HashMap<String, String>> = ctx.localize_data(HashMap::<String, String>::new()).unwrap();
+        assert_eq!(t.strings["hi"], "Hello");
+        assert_eq!(t.sources["hi"], "en-US");
+
+        let mut data = HashMap::new();
+        data.insert("user", "Ferris");
+        let t: Localized<HashMap<String, String>> = ctx.localize_data(&data).unwrap();
+        assert_eq!(t.strings["hi"], "Hallo, Ferris");
+        assert_eq!(t.sources["hi"], "de");
+
+        ctx.add_locale_resource("de", "<only '{{ $user }}'>").unwrap();
+        assert!(ctx.localize::<HashMap<String, String>>().is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let mut locale = Locale::new();
+        locale.add_resource(r#"
+        <brand 'Rust' long: 'Rust {{ $lang }}'>
+        <hi 'Hello, {{ brand }}!'>
+        <broken '{{ missing }}'>
+        "#).unwrap();
+
+        let mut data = HashMap::new();
+        data.insert("lang", "Lang");
+
+        assert_eq!(locale.format("hi", &data).unwrap(), "Hello, Rust!");
+        assert_eq!(locale.format_attr("brand", "long", &data).unwrap(), "Rust Lang");
+        assert!(locale.format("broken", &data).is_err());
+        assert!(locale.format("nope", &data).is_err());
+        assert!(locale.format_attr("brand", "short", &data).is_err());
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut locale = Locale::new();
+        locale.add_resource(r#"
+        <brand 'Rust'>
+        <add($a, $b) { $a + $b }>
+        <hi 'Hi {{ brand }}, {{ add($n, 1) }}'>
+        <broken '{{ nope }}'>
+        "#).unwrap();
+
+        let mut data = HashMap::new();
+        data.insert("n", 2);
+        let (result, steps) = locale.trace("hi", &data);
+        assert_eq!(result.unwrap(), "Hi Rust, 3");
+        let steps = steps.iter().map(|s| (s.depth, &s.expr[..], s.result.clone().unwrap())).collect::<Vec<_>>();
+        assert_eq!(steps, vec![
+            (0, "brand", data::Str(String::from("Rust"))),
+            (0, "add($n, 1)", data::Num(3)),
+            (1, "$n", data::Num(2)),
+            (1, "1", data::Num(1)),
+            (1, "$a", data::Num(2)),
+            (1, "$b", data::Num(1)),
+        ]);
+
+        let (result, steps) = locale.trace("broken", ());
+        assert!(result.is_err());
+        assert_eq!(steps.len(), 1);
+        assert!(steps[0].result.is_err());
+    }
+
+    #[test]
+    fn test_format_data() {
+        let mut locale = Locale::new();
+        locale.add_resource("<unread '{{ $user.name }} has {{ $count }} at {{ $when }}'>").unwrap();
+
+        let mut user = HashMap::new();
+        user.insert(String::from("name"), data::Data::from("Sean"));
+        let mut args = HashMap::new();
+        args.insert(String::from("user"), data::Map(user));
+        args.insert(String::from("count"), data::Data::from(3));
+        args.insert(String::from("when"), data::Data::from(::DateTime::from_timestamp(0)));
+        assert_eq!(locale.format("unread", data::Map(args)).unwrap(), "Sean has 3 at 1970 M01 1 00:00");
+    }
+
+    #[test]
+    fn test_localize_partial() {
+        let mut locale = Locale::new();
+        locale.add_resource(r#"
+        <hi 'Hello, {{ $name }}!'>
+        <bye 'Goodbye, {{ $missing }}!'>
+        <brand 'Rust'>
+        "#).unwrap();
+
+        let mut data = HashMap::new();
+        data.insert("name", "Rust");
+
+        let t: Partial<HashMap<String, String>> = locale.localize_data_partial(&data, Fallback::Source).unwrap();
+        assert_eq!(t.strings["hi"], "Hello, Rust!");
+        assert_eq!(t.strings["bye"], "Goodbye, {{ $missing }}!");
+        assert_eq!(t.strings["brand"], "Rust");
+        assert_eq!(t.errors.len(), 1);
+        assert_eq!(t.errors[0].0, "bye");
+
+        let t: Partial<HashMap<String, String>> = locale.localize_data_partial(&data, Fallback::Id).unwrap();
+        assert_eq!(t.strings["bye"], "bye");
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut locale = Locale::new();
+        locale.add_resource(r#"
+        <greeting 'Hi from {{ @app }} on {{ @os }}, {{ @user }}'>
+        <hour '{{ @hour >= 0 && @hour < 24 ? "valid" : "invalid" }}'>
+        "#).unwrap();
+        locale.register_global("app", "l20n").unwrap();
+        locale.register_global_fn("user", || "Ferris");
+
+        let greeting = locale.format("greeting", ()).unwrap();
+        assert!(greeting.starts_with("Hi from l20n on "));
+        assert!(greeting.ends_with(", Ferris"));
+        assert_eq!(locale.format("hour", ()).unwrap(), "valid");
+
+        locale.set_utc_offset(-12 * 60);
+        assert_eq!(locale.format("hour", ()).unwrap(), "valid");
+    }
+
+    #[test]
+    fn test_plural() {
+        let mut ctx = Context::with_fallbacks(vec!["pl", "en"]);
+        ctx.add_locale_resource("pl", r#"
+        <unread[plural($n)] {
+          one: '{{ $n }} wiadomość',
+          few: '{{ $n }} wiadomości',
+          *many: '{{ $n }} wiadomości'
+        }>
+        "#).unwrap();
+        ctx.add_locale_resource("en", r#"
+        <place "{{ $n }}{{ ordinal($n) == 'one' ? 'st' : ordinal($n) == 'two' ? 'nd' : 'th' }}">
+        <plural($n) { 'shadowed' }>
+        <custom "{{ plural(1) }}">
+        "#).unwrap();
+
+        let pl = ctx.get_locale("pl").unwrap();
+        assert_eq!(pl.tag(), "pl");
+        assert_eq!(pl.format("unread", vec![("n", 1)].into_iter().collect::<HashMap<_, _>>()).unwrap(), "1 wiadomość");
+        assert_eq!(pl.format("unread", vec![("n", 3)].into_iter().collect::<HashMap<_, _>>()).unwrap(), "3 wiadomości");
+
+        let en = ctx.get_locale("en").unwrap();
+        assert_eq!(en.format("place", vec![("n", 22)].into_iter().collect::<HashMap<_, _>>()).unwrap(), "22nd");
+        assert_eq!(en.format("custom", ()).unwrap(), "shadowed");
+    }
+
+    #[test]
+    fn test_register_fn() {
+        let mut locale = Locale::with_tag("en");
+        locale.add_resource(r#"
+        <greeting "Hello, {{ upper($name) }}! You have {{ count() }} items.">
+        <bad "{{ upper(1) }}">
+        "#).unwrap();
+        locale.register_fn("upper", |args| match args.first() {
+            Some(&data::Str(ref s)) if args.len() == 1 => Ok(data::Str(s.to_uppercase())),
+            _ => Err(compiler::FunctionFailed(String::from("expected one string")).into())
+        });
+        locale.register_fn("count", |_| Ok(data::Num(3)));
+
+        let mut data = HashMap::new();
+        data.insert("name", "ferris");
+        assert_eq!(locale.format("greeting", data).unwrap(), "Hello, FERRIS! You have 3 items.");
+        match locale.format("bad", ()) {
+            Err(ResolveError(compiler::ResolveError { kind: compiler::FunctionFailed(ref msg), .. })) => {
+                assert_eq!(msg, "expected one string")
+            }
+            other => panic!("expected FunctionFailed, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_float_data() {
+        let mut locale = Locale::new();
+        locale.add_resource("<price 'Total: {{ $price * $qty }} ({{ $rate * 100 }}%)'>").unwrap();
+        let mut data = HashMap::new();
+        data.insert("price", 2.25);
+        data.insert("qty", 3.0);
+        data.insert("rate", 0.125);
+        assert_eq!(locale.format("price", data).unwrap(), "Total: 6.75 (12.5%)");
+    }
+
+    #[test]
+    fn test_number_format() {
+        let mut ctx = Context::with_fallbacks(vec!["de", "en"]);
+        let src = r#"
+        <total "{{ $count }} items, {{ number($price, 'currency', 'EUR') }} ({{ number($off, 'percent') }} off)">
+        <ratio "{{ number($ratio, 'decimal', 1, 2) }}">
+        <sum "{{ 0.1 + 0.2 }} = {{ number(0.1 + 0.2) }}">
+        "#;
+        ctx.add_locale_resource("de", src).unwrap();
+        ctx.add_locale_resource("en", src).unwrap();
+
+        let mut data = HashMap::new();
+        data.insert("count", 12345.0);
+        data.insert("price", 1234.5);
+        data.insert("off", 0.15);
+        data.insert("ratio", 2.0);
+        let de = ctx.get_locale("de").unwrap();
+        let en = ctx.get_locale("en").unwrap();
+        assert_eq!(de.format("total", &data).unwrap(), "12.345 items, 1.234,50\u{a0}€ (15\u{a0}% off)");
+        assert_eq!(en.format("total", &data).unwrap(), "12,345 items, €1,234.50 (15% off)");
+        assert_eq!(en.format("ratio", &data).unwrap(), "2.0");
+        assert_eq!(de.format("sum", &data).unwrap(), "0,3 = 0,3");
+
+        let mut plain = Locale::new();
+        plain.add_resource(src).unwrap();
+        assert_eq!(plain.format("total", &data).unwrap(), "12345 items, €\u{a0}1,234.50 (15% off)");
+    }
+
+    #[test]
+    fn test_dates() {
+        let mut locale = Locale::with_tag("fr-FR");
+        locale.add_resource(r#"
+        <sent "Envoyé le {{ date($at, 'full') }} à {{ time($at) }}">
+        <seen "Vu {{ $at }}">
+        "#).unwrap();
+        let mut data = HashMap::new();
+        data.insert("at", ::datetime::DateTime::from_ymd_hms(2016, 7, 14, 8, 30, 0).unwrap().with_offset(120));
+        assert_eq!(locale.format("sent", &data).unwrap(), "Envoyé le jeudi 14 juillet 2016 à 10:30");
+        assert_eq!(locale.format("seen", &data).unwrap(), "Vu 14 juil. 2016, 10:30");
+    }
+
+    #[test]
+    fn test_add_resource_partial() {
+        let mut locale = Locale::new();
+        let errors = locale.add_resource_partial("<hi 'Hello'>\n<broken 'oops' :>\n<bye 'Goodbye'>");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+        assert_eq!(locale.format("hi", ()).unwrap(), "Hello");
+        assert_eq!(locale.format("bye", ()).unwrap(), "Goodbye");
+        assert!(locale.format("broken", ()).is_err());
+    }
+
+    #[test]
+    fn test_add_resource_dir() {
+        let dir = env::temp_dir().join(format!("l20n-test-add-resource-dir-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let write = |path: &str, source: &str| {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap().write_all(source.as_bytes()).unwrap();
+        };
+        write("en-US/main.l20n", "<hi 'Hello'>");
+        write("en-US/extra.l20n", "<bye 'Goodbye'>");
+        write("de/main.l20n", "<hi 'Hallo'>");
+        write("de/notes.txt", "not a resource");
+
+        let mut ctx = Context::with_fallbacks(vec!["de", "en-US"]);
+        ctx.add_resource_dir(&dir).unwrap();
+        let t: Localized<HashMap<String, String>> = ctx.localize().unwrap();
+        assert_eq!(t.strings["hi"], "Hallo");
+        assert_eq!(t.strings["bye"], "Goodbye");
+
+        write("fr/main.l20n", "<hi 'Salut'>\n<bye 'oops' :>");
+        let path = dir.join("fr").join("main.l20n");
+        match ctx.add_resource_dir(&dir) {
+            Err(LoadError::Parse(e)) => {
+                assert_eq!(e.path.as_ref(), Some(&path));
+                assert_eq!(e.line, 2);
+                assert!(e.to_string().ends_with(&format!(" in {}", path.display())));
+            }
+            other => panic!("expected a parse error, got {:?}", other)
+        }
+        match Locale::new().add_resource_file(dir.join("missing.l20n")) {
+            Err(LoadError::Io(p, _)) => assert_eq!(p, dir.join("missing.l20n")),
+            other => panic!("expected an IO error, got {:?}", other)
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_watcher() {
+        let dir = env::temp_dir().join(format!("l20n-test-watcher-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.l20n");
+        let write = |source: &str| File::create(&path).unwrap().write_all(source.as_bytes()).unwrap();
+        write("<hi 'Hello'>\n<old 'Old'>");
+
+        let mut locale = Locale::new();
+        locale.add_resource("<bye 'Goodbye'>").unwrap();
+        let mut watcher = Watcher::new(&locale);
+        watcher.add_file(&path).unwrap();
+        assert_eq!(locale.format("hi", ()).unwrap(), "Hello");
+        assert!(watcher.poll().is_empty());
+
+        write("<hi 'Hello again'>");
+        assert!(watcher.poll().is_empty());
+        assert_eq!(locale.format("hi", ()).unwrap(), "Hello again");
+        assert!(locale.format("old", ()).is_err());
+        assert_eq!(locale.format("bye", ()).unwrap(), "Goodbye");
+
+        write("<hi 'Broken' :>");
+        let errors = watcher.poll();
+        assert_eq!(errors.len(), 1);
+        match errors[0] {
+            LoadError::Parse(ref e) => assert_eq!(e.path.as_ref(), Some(&path)),
+            ref other => panic!("expected a parse error, got {:?}", other)
+        }
+        assert_eq!(locale.format("hi", ()).unwrap(), "Hello again");
+        assert!(watcher.poll().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_watcher_precedence() {
+        let dir = env::temp_dir().join(format!("l20n-test-watcher-precedence-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.l20n");
+        let write = |source: &str| File::create(&path).unwrap().write_all(source.as_bytes()).unwrap();
+        write("<hi 'Hello'>\n<bye 'Bye'>");
+
+        let mut locale = Locale::new();
+        let mut watcher = Watcher::new(&locale);
+        watcher.add_file(&path).unwrap();
+        locale.add_resource("<hi 'Overridden'>").unwrap();
+        write("<hi 'Hello again'>\n<bye 'Goodbye'>");
+        assert!(watcher.poll().is_empty());
+        assert_eq!(locale.format("hi", ()).unwrap(), "Overridden");
+        assert_eq!(locale.format("bye", ()).unwrap(), "Goodbye");
+
+        // Another Watcher doesn't keep the thread of this one running.
+        let _other = Watcher::new(&locale);
+        let handle = watcher.spawn(Duration::from_millis(10), |e| panic!("{}", e));
+        drop(locale);
+        handle.join().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resources() {
+        let compile = |source: &str| compiler::compile(source).unwrap();
+        let mut resources = Resources {
+            layers: vec![],
+            merged: Arc::new(compiler::Env::new()),
+        };
+        let first = resources.push(compile("<hi 'Hello'> <bye 'Bye'>"));
+        let unshared = &*resources.merged as *const compiler::Env;
+        let second = resources.push(compile("<hi 'Hi'>"));
+        assert_eq!(&*resources.merged as *const compiler::Env, unshared);
+        let merged = resources.merged.clone();
+        resources.push(compile("<yes 'Yes'>"));
+        assert_eq!(merged.len(), 2);
+        assert_eq!(resources.merged.len(), 3);
+
+        resources.replace(second, compile("<no 'No'>"));
+        assert_eq!(format!("{}", resources.merged["hi"]), "<hi \"Hello\">");
+        resources.replace(first, compile("<bye 'Goodbye'>"));
+        assert!(!resources.merged.contains_key("hi"));
+        assert_eq!(format!("{}", resources.merged["bye"]), "<bye \"Goodbye\">");
+        assert_eq!(resources.merged.len(), 3);
+    }
+
+    struct MapLoader(HashMap<PathBuf, &'static str>);
+
+    impl Loader for MapLoader {
+        fn load(&self, path: &Path) -> io::Result<String> {
+            match self.0.get(path) {
+                Some(res) => Ok(res.to_string()),
+                None => Err(io::Error::new(io::ErrorKind::NotFound, "not found"))
+            }
+        }
+    }
+
+    #[test]
+    fn test_imports() {
+        let mut files = HashMap::new();
+        files.insert(PathBuf::from("shared/brand.l20n"), "<brand 'Rust'>\n<hi 'Hi'>");
+        files.insert(PathBuf::from("en/main.l20n"), "import('../shared/brand.l20n')\n<hi 'Hello from {{ brand }}'>");
+        files.insert(PathBuf::from("en/a.l20n"), "<a 'A'>\n  import('./b.l20n')");
+        files.insert(PathBuf::from("en/b.l20n"), "import('a.l20n')");
+        files.insert(PathBuf::from("en/missing.l20n"), "import('nope.l20n')");
+        let loader = MapLoader(files);
+
+        let mut locale = Locale::new();
+        locale.add_resource_with(&loader, "en/main.l20n").unwrap();
+        assert_eq!(locale.format("hi", ()).unwrap(), "Hello from Rust");
+
+        match locale.add_resource_with(&loader, "en/a.l20n") {
+            Err(e @ LoadError::Import(..)) => {
+                assert_eq!(e.to_string(), "en/a.l20n:2:3: en/b.l20n:1:1: Import cycle: en/a.l20n -> en/b.l20n -> en/a.l20n");
+            }
+            other => panic!("expected an import error, got {:?}", other)
+        }
+        match locale.add_resource_with(&loader, "en/missing.l20n") {
+            Err(LoadError::Import(path, pos, e)) => {
+                assert_eq!((path, pos.line, pos.col), (PathBuf::from("en/missing.l20n"), 1, 1));
+                match *e {
+                    LoadError::Io(ref path, _) => assert_eq!(path, &PathBuf::from("en/nope.l20n")),
+                    ref other => panic!("expected an IO error, got {:?}", other)
+                }
+            }
+            other => panic!("expected an import error, got {:?}", other)
+        }
+    }
 }
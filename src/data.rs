@@ -5,21 +5,35 @@ use std::fmt;
 
 use serde;
 
+use datetime;
+
 pub use self::Data::*;
 
-/// An internal Data format used to resolve L20n resources.
-#[doc(hidden)]
+/// The values L20n expressions work with. Data passed in to localize is
+/// encoded into this format, and functions registered with
+/// `Locale::register_fn` receive and return it.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Data {
+    /// No value.
     Null,
+    /// A boolean.
     Bool(bool),
+    /// An integer.
     Num(i64),
+    /// A floating-point number.
+    Float(f64),
+    /// A moment in time.
+    DateTime(datetime::DateTime),
+    /// A string.
     Str(String),
+    /// A list of values.
     List(Vec<Data>),
+    /// A map from names to values, such as a struct.
     Map(HashMap<String, Data>),
 }
 
 impl Data {
+    /// Gets the value of `key` if this is a Map.
     pub fn get(&self, key: &str) -> Option<&Data> {
         match *self {
             Data::Map(ref map) => map.get(key),
@@ -28,6 +42,53 @@ impl Data {
     }
 }
 
+impl serde::Serialize for Data {
+    fn serialize<S: serde::Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        match *self {
+            Null => serializer.visit_unit(),
+            Bool(b) => serializer.visit_bool(b),
+            Num(n) => serializer.visit_i64(n),
+            Float(n) => serializer.visit_f64(n),
+            DateTime(ref dt) => dt.serialize(serializer),
+            Str(ref s) => serializer.visit_str(s),
+            List(ref list) => list.serialize(serializer),
+            Map(ref map) => map.serialize(serializer),
+        }
+    }
+}
+
+impl From<bool> for Data {
+    fn from(b: bool) -> Data { Bool(b) }
+}
+
+impl From<i32> for Data {
+    fn from(n: i32) -> Data { Num(n as i64) }
+}
+
+impl From<i64> for Data {
+    fn from(n: i64) -> Data { Num(n) }
+}
+
+impl From<u32> for Data {
+    fn from(n: u32) -> Data { Num(n as i64) }
+}
+
+impl From<f64> for Data {
+    fn from(n: f64) -> Data { Float(n) }
+}
+
+impl From<datetime::DateTime> for Data {
+    fn from(dt: datetime::DateTime) -> Data { DateTime(dt) }
+}
+
+impl<'a> From<&'a str> for Data {
+    fn from(s: &'a str) -> Data { Str(s.to_string()) }
+}
+
+impl From<String> for Data {
+    fn from(s: String) -> Data { Str(s) }
+}
+
 #[doc(hidden)]
 pub struct Encoder {
     data: Vec<Data>
@@ -82,19 +143,39 @@ impl serde::Serializer for Encoder {
     fn visit_bool(&mut self, v: bool) -> EncoderResult { self.data.push(Bool(v)); Ok(()) }
     fn visit_i64(&mut self, v: i64) -> EncoderResult { self.data.push(Num(v)); Ok(()) }
     fn visit_u64(&mut self, v: u64) -> EncoderResult { self.visit_i64(v as i64) }
-    fn visit_f64(&mut self, v: f64) -> EncoderResult { self.visit_i64(v as i64) }
+    fn visit_f64(&mut self, v: f64) -> EncoderResult { self.data.push(Float(v)); Ok(()) }
 
     fn visit_str(&mut self, v: &str) -> EncoderResult {
         self.data.push(Str(v.to_string()));
         Ok(())
     }
 
+    fn visit_newtype_struct<T>(&mut self, name: &'static str, v: T) -> EncoderResult where T: serde::Serialize {
+        if name != datetime::SERDE_NAME {
+            return self.visit_tuple_struct(name, Some(v));
+        }
+        let mut encoder = Encoder::new();
+        try!(v.serialize(&mut encoder));
+        match encoder.data() {
+            Some(List(ref fields)) if fields.len() == 2 => match (&fields[0], &fields[1]) {
+                (&Num(timestamp), &Num(offset)) => {
+                    let dt = datetime::DateTime::from_timestamp(timestamp).with_offset(offset as i32);
+                    self.data.push(DateTime(dt));
+                    Ok(())
+                }
+                _ => Err(EncodeError::UnsupportedType)
+            },
+            _ => Err(EncodeError::UnsupportedType)
+        }
+    }
+
     fn visit_unit(&mut self) -> EncoderResult { self.data.push(Null); Ok(()) }
     fn visit_none(&mut self) -> EncoderResult { self.visit_unit() }
     fn visit_some<V>(&mut self, v: V) -> EncoderResult where V: serde::Serialize { v.serialize(self) }
     fn visit_seq<V>(&mut self, mut v: V) -> EncoderResult where V: serde::ser::SeqVisitor {
         self.data.push(List(vec![]));
-        v.visit(self).map(|_| ())
+        while let Some(()) = try!(v.visit(self)) {}
+        Ok(())
     }
     fn visit_seq_elt<V>(&mut self, v: V) -> EncoderResult where V: serde::Serialize {
         match self.data.pop() {
@@ -110,7 +191,8 @@ impl serde::Serializer for Encoder {
     }
     fn visit_map<V>(&mut self, mut v: V) -> EncoderResult where V: serde::ser::MapVisitor {
         self.data.push(Map(HashMap::new()));
-        v.visit(self).map(|_| ())
+        while let Some(()) = try!(v.visit(self)) {}
+        Ok(())
     }
     fn visit_map_elt<K, V>(&mut self, k: K, v: V) -> EncoderResult where K: serde::Serialize, V: serde::Serialize {
         match self.data.pop() {
@@ -155,6 +237,8 @@ impl serde::Deserializer for Decoder {
             Some(Data::Null) => visitor.visit_unit(),
             Some(Data::Bool(b)) => visitor.visit_bool(b),
             Some(Data::Num(n)) => visitor.visit_i64(n),
+            Some(Data::Float(n)) => visitor.visit_f64(n),
+            Some(Data::DateTime(dt)) => visitor.visit_string(dt.to_string()),
             Some(Data::Str(s)) => visitor.visit_str(&s),
             Some(Data::List(list)) => {
                 let len = list.len();
@@ -175,3 +259,32 @@ impl serde::de::value::ValueDeserializer for Data {
         Decoder::new(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Serialize;
+
+    use super::{Data, Encoder, List, Map, Num, Str};
+
+    fn encode<T: Serialize>(value: T) -> Data {
+        let mut encoder = Encoder::new();
+        value.serialize(&mut encoder).unwrap();
+        encoder.data().unwrap()
+    }
+
+    #[test]
+    fn test_encode_seq_and_map() {
+        assert_eq!(encode(vec![1, 2, 3]), List(vec![Num(1), Num(2), Num(3)]));
+        assert_eq!(encode(Vec::<i64>::new()), List(vec![]));
+
+        let mut names = HashMap::new();
+        names.insert("first", vec!["Ada"]);
+        names.insert("last", vec!["Lovelace", "Byron"]);
+        let mut expected = HashMap::new();
+        expected.insert(String::from("first"), List(vec![Str(String::from("Ada"))]));
+        expected.insert(String::from("last"), List(vec![Str(String::from("Lovelace")), Str(String::from("Byron"))]));
+        assert_eq!(encode(names), Map(expected));
+    }
+}
@@ -0,0 +1,405 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use icu_calendar::{Date, Gregorian};
+use icu_datetime::FixedCalendarDateTimeFormatter as DateTimeFormatter;
+use icu_datetime::fieldsets::builder::{DateFields, FieldSetBuilder, ZoneStyle};
+use icu_datetime::fieldsets::enums::CompositeFieldSet;
+use icu_datetime::options::{Length, TimePrecision};
+use icu_locale_core::Locale;
+use icu_time::{Time, TimeZone, ZonedDateTime};
+use icu_time::zone::UtcOffset;
+use icu_time;
+use serde;
+
+use compiler::{Functions, NativeFn, ResolveError};
+use compiler;
+use data;
+use plural;
+
+/// The name `DateTime` serializes itself under, which the Encoder looks
+/// for to produce `Data::DateTime`.
+pub const SERDE_NAME: &'static str = "$l20n::DateTime";
+
+/// A moment in time, along with the offset from UTC to show it in.
+///
+/// Pass a DateTime in the data given to a Locale, and resources can format
+/// it with the built-in `date`, `time` and `datetime` macros:
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use l20n::{DateTime, Locale};
+///
+/// let mut locale = Locale::with_tag("en-US");
+/// locale.add_resource("<due 'Due {{ date($when, \"long\") }}'>").unwrap();
+/// let mut data = HashMap::new();
+/// data.insert("when", DateTime::from_ymd_hms(2016, 2, 29, 12, 0, 0).unwrap());
+/// assert_eq!(locale.format("due", data).unwrap(), "Due February 29, 2016");
+/// ```
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DateTime {
+    timestamp: i64,
+    offset: i32,
+}
+
+impl DateTime {
+    /// The moment `secs` seconds after the Unix epoch, shown in UTC.
+    pub fn from_timestamp(secs: i64) -> DateTime {
+        DateTime {
+            timestamp: secs,
+            offset: 0,
+        }
+    }
+
+    /// The moment at the date and time given in UTC, if they are valid.
+    pub fn from_ymd_hms(year: i64, month: u32, day: u32, hour: u32, min: u32, sec: u32) -> Option<DateTime> {
+        if month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) ||
+                hour > 23 || min > 59 || sec > 59 {
+            return None;
+        }
+        let days = days_from_civil(year, month, day);
+        Some(DateTime::from_timestamp(days * 86400 + (hour * 3600 + min * 60 + sec) as i64))
+    }
+
+    /// The current moment, shown in UTC.
+    pub fn now() -> DateTime {
+        DateTime::from_timestamp(match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64)
+        })
+    }
+
+    /// The same moment, shown at `minutes` east of UTC.
+    pub fn with_offset(self, minutes: i32) -> DateTime {
+        DateTime {
+            timestamp: self.timestamp,
+            offset: minutes,
+        }
+    }
+
+    /// Seconds since the Unix epoch.
+    pub fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    /// Minutes east of UTC.
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+
+    // The local calendar fields: year, month, day, weekday (0 is Sunday),
+    // hour, minute and second.
+    fn fields(&self) -> (i64, u32, u32, u32, u32, u32, u32) {
+        let local = self.timestamp + self.offset as i64 * 60;
+        let days = div_floor(local, 86400);
+        let secs = (local - days * 86400) as u32;
+        let (year, month, day) = civil_from_days(days);
+        let weekday = (days + 4 - div_floor(days + 4, 7) * 7) as u32;
+        (year, month, day, weekday, secs / 3600, secs / 60 % 60, secs % 60)
+    }
+}
+
+/// Shows the DateTime in RFC 3339 format, such as
+/// `2016-01-02T15:04:05+01:00`.
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (year, month, day, _, hour, min, sec) = self.fields();
+        try!(write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", year, month, day, hour, min, sec));
+        match self.offset {
+            0 => write!(f, "Z"),
+            offset => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                write!(f, "{}{:02}:{:02}", sign, offset.abs() / 60, offset.abs() % 60)
+            }
+        }
+    }
+}
+
+impl serde::Serialize for DateTime {
+    fn serialize<S: serde::Serializer>(&self, serializer: &mut S) -> Result<(), S::Error> {
+        serializer.visit_newtype_struct(SERDE_NAME, (self.timestamp, self.offset as i64))
+    }
+}
+
+fn div_floor(a: i64, b: i64) -> i64 {
+    let d = a / b;
+    if (a % b != 0) && ((a < 0) != (b < 0)) { d - 1 } else { d }
+}
+
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+// Days since 1970-01-01 in the proleptic Gregorian calendar, from Howard
+// Hinnant's `chrono`-compatible date algorithms.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = div_floor(y, 400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = div_floor(z, 146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// The length of a date or time format, as in CLDR.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    /// Such as `1/2/16` or `3:04 PM`.
+    Short,
+    /// Such as `Jan 2, 2016` or `3:04:05 PM`.
+    Medium,
+    /// Such as `January 2, 2016` or `3:04:05 PM GMT`.
+    Long,
+    /// Such as `Saturday, January 2, 2016`.
+    Full,
+}
+
+impl Style {
+    fn from_name(name: &str) -> Option<Style> {
+        match name {
+            "short" => Some(Style::Short),
+            "medium" => Some(Style::Medium),
+            "long" => Some(Style::Long),
+            "full" => Some(Style::Full),
+            _ => None
+        }
+    }
+
+    // The CLDR length of a date, where `full` is the long date with the
+    // weekday.
+    fn date_fields(self) -> (DateFields, Length) {
+        match self {
+            Style::Full => (DateFields::YMDE, Length::Long),
+            Style::Long => (DateFields::YMD, Length::Long),
+            Style::Medium => (DateFields::YMD, Length::Medium),
+            Style::Short => (DateFields::YMD, Length::Short),
+        }
+    }
+
+    // How precise a time is, and how its offset is shown.
+    fn time_fields(self) -> (TimePrecision, Option<ZoneStyle>) {
+        match self {
+            Style::Full => (TimePrecision::Second, Some(ZoneStyle::LocalizedOffsetLong)),
+            Style::Long => (TimePrecision::Second, Some(ZoneStyle::LocalizedOffsetShort)),
+            Style::Medium => (TimePrecision::Second, None),
+            Style::Short => (TimePrecision::Minute, None),
+        }
+    }
+}
+
+/// Formats dates and times with the CLDR patterns and names of a locale.
+///
+/// The data comes from ICU4X, which has CLDR data for the same languages
+/// as the plural rules, and their regional variants, so `pt-PT` and
+/// `en-GB` get patterns of their own. Other languages, and `i-default`,
+/// get the CLDR root patterns.
+#[derive(Debug, Clone)]
+pub struct DateFormat {
+    locale: Locale,
+    fallback: bool,
+}
+
+impl DateFormat {
+    /// The date format for a language tag. Languages CLDR doesn't know get
+    /// the CLDR root patterns, such as `2016 M01 2`.
+    pub fn new(tag: &str) -> DateFormat {
+        let langid = tag.replace('_', "-");
+        let locale = match Locale::try_from_str(&langid) {
+            _ if tag == "i-default" => Locale::UNKNOWN,
+            Ok(locale) => locale,
+            Err(_) => Locale::UNKNOWN
+        };
+        DateFormat {
+            locale: locale,
+            fallback: tag != "i-default" && !plural::is_known(&langid),
+        }
+    }
+
+    /// Whether CLDR doesn't know the language of the tag, so dates get the
+    /// CLDR root patterns.
+    pub fn is_fallback(&self) -> bool {
+        self.fallback
+    }
+
+    /// Formats the date and time, either of which may be left out. The
+    /// length of the date decides how the two are joined. Returns None if
+    /// the year is out of the range CLDR formats.
+    pub fn format(&self, dt: &DateTime, date: Option<Style>, time: Option<Style>) -> Option<String> {
+        if date.is_none() && time.is_none() {
+            return Some(String::new());
+        }
+        let mut fields = FieldSetBuilder::new();
+        if let Some(date) = date {
+            let (date_fields, length) = date.date_fields();
+            fields.date_fields = Some(date_fields);
+            fields.length = Some(length);
+        }
+        if let Some(time) = time {
+            let (precision, zone) = time.time_fields();
+            fields.time_precision = Some(precision);
+            fields.zone_style = zone;
+        }
+        let fields = match fields.build_composite() {
+            Ok(fields) => fields,
+            Err(_) => return None
+        };
+        let formatter = match DateTimeFormatter::<Gregorian, CompositeFieldSet>::try_new((&self.locale).into(), fields) {
+            Ok(formatter) => formatter,
+            Err(_) => return None
+        };
+        let (year, month, day, _, hour, min, sec) = dt.fields();
+        if year < i32::min_value() as i64 || year > i32::max_value() as i64 {
+            return None;
+        }
+        let date = match Date::try_new_gregorian(year as i32, month as u8, day as u8) {
+            Ok(date) => date,
+            Err(_) => return None
+        };
+        let time = match Time::try_new(hour as u8, min as u8, sec as u8, 0) {
+            Ok(time) => time,
+            Err(_) => return None
+        };
+        let offset = UtcOffset::try_from_seconds(dt.offset * 60).ok();
+        let zone = TimeZone::UNKNOWN.with_offset(offset).at_date_time(icu_time::DateTime {
+            date: date,
+            time: time,
+        });
+        Some(formatter.format(&ZonedDateTime {
+            date: date,
+            time: time,
+            zone: zone,
+        }).to_string())
+    }
+
+    /// Formats a date and time as it is interpolated into a string.
+    pub fn format_default(&self, dt: &DateTime) -> Option<String> {
+        self.format(dt, Some(Style::Medium), Some(Style::Short))
+    }
+}
+
+/// Registers the `date`, `time` and `datetime` macros, which format a
+/// DateTime in one of the `short`, `medium`, `long` or `full` styles, with
+/// the CLDR patterns of the locale:
+///
+/// ```text
+/// date($d)                       Jan 2, 2016
+/// date($d, 'full')               Saturday, January 2, 2016
+/// time($d, 'short')              3:04 PM
+/// datetime($d, 'long', 'short')  January 2, 2016 at 3:04 PM
+/// ```
+///
+/// `date` defaults to `medium`, `time` to `short`, and `datetime` to both.
+pub fn register(functions: &mut Functions, format: DateFormat) {
+    functions.insert("date", date_fn(format.clone(), true, false));
+    functions.insert("time", date_fn(format.clone(), false, true));
+    functions.insert("datetime", date_fn(format, true, true));
+}
+
+fn date_fn(format: DateFormat, date: bool, time: bool) -> NativeFn {
+    Box::new(move |args: &[data::Data]| {
+        let arity = 1 + date as usize + time as usize;
+        if args.is_empty() || args.len() > arity {
+            return Err(ResolveError::unplaced(compiler::WrongNumberOfArgs));
+        }
+        let dt = match args[0] {
+            data::DateTime(dt) => dt,
+            _ => return Err(ResolveError::unplaced(compiler::WrongType))
+        };
+        let mut styles = vec![];
+        for arg in &args[1..] {
+            match *arg {
+                data::Str(ref name) => match Style::from_name(name) {
+                    Some(style) => styles.push(style),
+                    None => {
+                        let msg = format!("unknown date style '{}'", name);
+                        return Err(compiler::FunctionFailed(msg).into());
+                    }
+                },
+                _ => return Err(ResolveError::unplaced(compiler::WrongType))
+            }
+        }
+        let mut styles = styles.into_iter();
+        let date_style = if date { Some(styles.next().unwrap_or(Style::Medium)) } else { None };
+        let time_style = if time { Some(styles.next().unwrap_or(Style::Short)) } else { None };
+        match format.format(&dt, date_style, time_style) {
+            Some(s) => Ok(data::Str(s)),
+            None => Err(compiler::FunctionFailed(format!("cannot format the year of {}", dt)).into())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DateFormat, DateTime, Style};
+
+    #[test]
+    fn test_calendar() {
+        let dt = DateTime::from_ymd_hms(2016, 2, 29, 23, 5, 9).unwrap();
+        assert_eq!(dt.timestamp(), 1456787109);
+        assert_eq!(dt.fields(), (2016, 2, 29, 1, 23, 5, 9));
+        assert_eq!(dt.with_offset(90).fields(), (2016, 3, 1, 2, 0, 35, 9));
+        assert_eq!(DateTime::from_timestamp(-1).fields(), (1969, 12, 31, 3, 23, 59, 59));
+        assert_eq!(DateTime::from_ymd_hms(2015, 2, 29, 0, 0, 0), None);
+        assert_eq!(dt.with_offset(-330).to_string(), "2016-02-29T17:35:09-05:30");
+    }
+
+    #[test]
+    fn test_styles() {
+        let dt = DateTime::from_ymd_hms(2016, 1, 2, 15, 4, 5).unwrap().with_offset(60);
+        let en = DateFormat::new("en-US");
+        let de = DateFormat::new("de-AT");
+        assert_eq!(en.format(&dt, Some(Style::Short), None).unwrap(), "1/2/16");
+        assert_eq!(en.format(&dt, Some(Style::Full), None).unwrap(), "Saturday, January 2, 2016");
+        assert_eq!(en.format(&dt, None, Some(Style::Long)).unwrap(), "4:04:05\u{202f}PM GMT+1");
+        assert_eq!(en.format(&dt, Some(Style::Long), Some(Style::Short)).unwrap(),
+                   "January 2, 2016 at 4:04\u{202f}PM");
+        assert_eq!(de.format(&dt, Some(Style::Long), Some(Style::Short)).unwrap(), "2. Jänner 2016 um 16:04");
+        assert_eq!(DateFormat::new("i-default").format_default(&dt).unwrap(), "2016 M01 2 16:04");
+    }
+
+    #[test]
+    fn test_regions() {
+        let dt = DateTime::from_ymd_hms(2016, 1, 2, 15, 4, 5).unwrap().with_offset(60);
+        let date = |tag| DateFormat::new(tag).format(&dt, Some(Style::Medium), None).unwrap();
+        assert_eq!(date("en-US"), "Jan 2, 2016");
+        assert_eq!(date("en-001"), "2 Jan 2016");
+        assert_eq!(date("en-AU"), date("en-001"));
+        assert_eq!(DateFormat::new("en-GB").format(&dt, None, Some(Style::Short)).unwrap(), "16:04");
+        assert_eq!(date("pt-BR"), "2 de jan. de 2016");
+        assert_eq!(date("pt-PT"), "02/01/2016");
+    }
+
+    #[test]
+    fn test_fallback() {
+        let dt = DateTime::from_ymd_hms(2016, 1, 2, 15, 4, 5).unwrap();
+        assert!(!DateFormat::new("pt-BR").is_fallback());
+        assert!(!DateFormat::new("pl").is_fallback());
+        assert!(!DateFormat::new("i-default").is_fallback());
+        let tlh = DateFormat::new("tlh");
+        assert!(tlh.is_fallback());
+        assert_eq!(tlh.format_default(&dt).unwrap(), "2016 M01 2 15:04");
+    }
+}
@@ -0,0 +1,289 @@
+use std::error;
+use std::error::Error as _StdError;
+use std::fmt;
+
+use ast::{self, Entry, Pos, Value};
+use parser::{ParseError, Parser};
+
+// Hashes longer than this are written one member per line.
+const WIDTH: usize = 80;
+const INDENT: &'static str = "  ";
+
+// Plural categories are written in CLDR order, before any other keys.
+const CATEGORIES: &'static [&'static str] = &["zero", "one", "two", "few", "many", "other"];
+
+/// Formats an L20n resource in the canonical style:
+///
+/// - Each entry starts on its own line, with at most one blank line
+///   between entries. Comments are kept as they are.
+/// - Attributes go on their own lines, indented by two spaces.
+/// - Expressions have single spaces around operators and inside `{{ }}`.
+/// - Strings use double quotes unless they contain one. Strings with line
+///   breaks are triple-quoted, starting on the line after the opening
+///   quotes and indented one level, unless their lines start with
+///   whitespace, which triple quotes would drop.
+/// - Hash keys are sorted, with plural categories in CLDR order. Hashes
+///   that don't fit in 80 columns have one member per line.
+///
+/// ```rust
+/// let src = "<unread[plural($n)] {other:'{{$n}} messages',*one :\"{{ $n }} message\"}>";
+/// assert_eq!(l20n::format(src).unwrap(),
+///            "<unread[plural($n)] { *one: \"{{ $n }} message\", other: \"{{ $n }} messages\" }>\n");
+/// ```
+///
+/// Each formatted entry is parsed back and compared with the original, and
+/// if it would mean something else, formatting fails rather than change a
+/// translation.
+pub fn format(source: &str) -> Result<String, FormatError> {
+    let entries = try!(Parser::new(source.chars()).parse());
+    format_entries(&entries)
+}
+
+/// Formats entries in the same style as `format`, such as after changing
+/// the ones returned by `l20n::parse`.
+pub fn format_entries(entries: &[Entry]) -> Result<String, FormatError> {
+    let mut printer = Printer { out: String::new() };
+    let mut last_line = None;
+    for entry in entries {
+        if let Some(line) = last_line {
+            if entry.span.start.line > line + 1 {
+                printer.out.push('\n');
+            }
+        }
+        let start = printer.out.len();
+        printer.entry(entry);
+        try!(check(entry, &printer.out[start..]));
+        printer.out.push('\n');
+        last_line = Some(entry.span.end.line);
+    }
+    Ok(printer.out)
+}
+
+fn check(entry: &Entry, formatted: &str) -> Result<(), FormatError> {
+    match Parser::new(formatted.chars()).parse() {
+        Ok(ref parsed) if ast::normalized(parsed.clone()) == ast::normalized(vec![entry.clone()]) => Ok(()),
+        _ => Err(FormatError::Changed(entry.span.start))
+    }
+}
+
+/// An error formatting a resource.
+#[derive(Debug)]
+pub enum FormatError {
+    /// The resource couldn't be parsed.
+    Parse(ParseError),
+    /// The formatted entry starting at this position would parse to
+    /// something else than the original.
+    Changed(Pos),
+}
+
+impl From<ParseError> for FormatError {
+    fn from(err: ParseError) -> FormatError {
+        FormatError::Parse(err)
+    }
+}
+
+impl error::Error for FormatError {
+    fn description(&self) -> &str {
+        match *self {
+            FormatError::Parse(_) => "Parse error",
+            FormatError::Changed(_) => "Formatting would change the meaning of an entry",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            FormatError::Parse(ref err) => Some(err),
+            FormatError::Changed(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FormatError::Parse(ref err) => write!(f, "{}: {}", self.description(), err),
+            FormatError::Changed(pos) => {
+                write!(f, "{} on line {}, column {}", self.description(), pos.line, pos.col)
+            }
+        }
+    }
+}
+
+struct Printer {
+    out: String,
+}
+
+impl Printer {
+    fn col(&self) -> usize {
+        let line = match self.out.rfind('\n') {
+            Some(i) => &self.out[i + 1..],
+            None => &self.out[..]
+        };
+        line.chars().count()
+    }
+
+    fn indent(&mut self, level: usize) {
+        for _ in 0..level {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    fn entry(&mut self, entry: &Entry) {
+        match entry.kind {
+            ast::Entity(ref id, ref value, ref index, ref attrs) => {
+                self.out.push('<');
+                self.out.push_str(id);
+                self.index(index);
+                self.out.push(' ');
+                self.value(value, 0);
+                for attr in attrs {
+                    self.out.push('\n');
+                    self.indent(1);
+                    self.out.push_str(&attr.0);
+                    self.index(&attr.2);
+                    self.out.push_str(": ");
+                    self.value(&attr.1, 1);
+                }
+                self.out.push('>');
+            }
+            ast::Macro(..) | ast::Comment(_) | ast::Import(_) => self.out.push_str(&entry.to_string()),
+        }
+    }
+
+    fn index(&mut self, index: &[ast::Expr]) {
+        if index.is_empty() {
+            return;
+        }
+        let exprs = index.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        self.out.push('[');
+        self.out.push_str(&exprs.join(", "));
+        self.out.push(']');
+    }
+
+    // Writes a value that starts on a line indented by `level`.
+    fn value(&mut self, value: &Value, level: usize) {
+        match value.kind {
+            ast::Str(_) | ast::ComplexStr(_) => match value.kind.tristr() {
+                Some((ref text, quote)) if text.contains('\n') => self.tristr(text, quote, level),
+                _ => self.out.push_str(&value.to_string())
+            },
+            ast::Hash(ref map, ref default, _) => {
+                let mut keys = map.keys().collect::<Vec<_>>();
+                keys.sort_by_key(|k| (CATEGORIES.iter().position(|c| c == k).unwrap_or(CATEGORIES.len()), *k));
+                let key = |k: &String| match *default {
+                    Some(ref d) if d == k => format!("*{}", k),
+                    _ => k.clone()
+                };
+
+                let mut members = vec![];
+                for k in &keys {
+                    let mut printer = Printer { out: String::new() };
+                    printer.value(&map[*k], 0);
+                    members.push(format!("{}: {}", key(k), printer.out));
+                }
+                let line = format!("{{ {} }}", members.join(", "));
+                // Leave room for the `>` closing the entry.
+                if !line.contains('\n') && self.col() + line.chars().count() < WIDTH {
+                    self.out.push_str(&line);
+                    return;
+                }
+
+                self.out.push('{');
+                for (i, k) in keys.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push(',');
+                    }
+                    self.out.push('\n');
+                    self.indent(level + 1);
+                    self.out.push_str(&key(k));
+                    self.out.push_str(": ");
+                    self.value(&map[*k], level + 1);
+                }
+                self.out.push('\n');
+                self.indent(level);
+                self.out.push('}');
+            }
+        }
+    }
+
+    fn tristr(&mut self, text: &str, quote: &str, level: usize) {
+        self.out.push_str(quote);
+        for line in text.split('\n') {
+            self.out.push('\n');
+            if !line.is_empty() {
+                self.indent(level + 1);
+                self.out.push_str(line);
+            }
+        }
+        self.out.push('\n');
+        self.indent(level);
+        self.out.push_str(quote);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format, FormatError};
+    use ast::{normalized, Entity, Entry, Span, Str, Value};
+    use parser::Parser;
+
+    const SOURCES: &'static [&'static str] = &[
+        r#"
+/* Branding */
+<brand 'Rust' short :  "R"    long:'The "Rust" language'>
+
+
+
+<unread[plural($n)] {other:'{{$n}} messages',many:'{{ $n}} messages', one: 'One message', few: '{{ $n }} messages'} title: { masculine: 'Unread messages for him', feminine: 'Unread messages for her' }>
+<about """
+      First line
+      {{brand}} second line
+   """>
+<add($a,$b){$a+$b}>
+"#,
+        r#"<a """it's "x" """>"#,
+        "<f \"  lead\nsecond\">",
+        "<g 'first\n\n  indented {{ $n }}'>",
+        r#"<h """
+  it's "{{ $n }}"
+  done
+""" title: { one: 'a
+b', *other: "  c" }>"#,
+        "import('shared.l20n')\n/* a comment */\n\n\n<x '{{ ($a + $b) * $c }}'>",
+    ];
+
+    #[test]
+    fn test_format_idempotent() {
+        for src in SOURCES {
+            let out = format(src).unwrap();
+            assert_eq!(format(&out).unwrap(), out, "formatting {:?} again", out);
+        }
+    }
+
+    #[test]
+    fn test_format_keeps_meaning() {
+        for src in SOURCES {
+            let out = format(src).unwrap();
+            assert_eq!(normalized(Parser::new(out.chars()).parse().unwrap()),
+                       normalized(Parser::new(src.chars()).parse().unwrap()),
+                       "{:?} formatted as {:?}", src, out);
+        }
+    }
+
+    #[test]
+    fn test_format_style() {
+        let out = format("<brand 'Rust' short :  \"R\"    long:'The \"Rust\" language'>\n<about '''\n   First\n   Second'''>").unwrap();
+        assert_eq!(out, "<brand \"Rust\"\n  short: \"R\"\n  long: 'The \"Rust\" language'>\n<about \"\"\"\n  First\n  Second\n\"\"\">\n");
+    }
+
+    #[test]
+    fn test_format_refuses_changes() {
+        // Plain strings have no escapes, so this can't be written back.
+        let value = Value::new(Str(String::from("a \\ b")), Span::default());
+        let entry = Entry::new(Entity(String::from("x"), value, vec![], vec![]), Span::default());
+        match super::format_entries(&[entry]) {
+            Err(FormatError::Changed(_)) => {}
+            other => panic!("expected Changed, got {:?}", other)
+        }
+    }
+}
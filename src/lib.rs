@@ -27,13 +27,48 @@
 #![cfg_attr(test, deny(warnings))]
 
 extern crate serde;
+extern crate intl_pluralrules;
+extern crate unic_langid;
+extern crate fixed_decimal;
+extern crate icu_calendar;
+extern crate icu_datetime;
+extern crate icu_decimal;
+extern crate icu_experimental;
+extern crate icu_locale_core;
+extern crate icu_time;
 
-pub use context::{Locale, LocalizeResult, LocalizeError};
-pub use data::{EncodeError};
-pub use compiler::ResolveError;
+pub use context::{Context, Fallback, Locale, Localized, LocalizeResult, LocalizeError, LoadError, Loader, FileLoader, Partial, Watcher};
+pub use data::{Data, EncodeError};
+pub use datetime::DateTime;
+pub use format::{format, format_entries, FormatError};
+pub use compiler::{ResolveError, ResolveErrorKind, TraceStep};
+pub use ast::{Pos, Span};
 pub use parser::{ParseError, ParseErrorKind};
 
+pub mod ast;
+pub mod build;
+pub mod compare;
+pub mod lint;
+
 mod compiler;
 mod data;
+mod datetime;
+mod format;
 mod context;
+mod number;
 mod parser;
+mod plural;
+
+/// Parses an L20n resource into its entries, including comments.
+///
+/// ```rust
+/// let entries = l20n::parse("/* greeting */ <hi 'Hello'>").unwrap();
+/// assert_eq!(entries.len(), 2);
+/// match entries[1].kind {
+///     l20n::ast::Entity(ref id, ..) => assert_eq!(id, "hi"),
+///     _ => unreachable!()
+/// }
+/// ```
+pub fn parse(source: &str) -> Result<Vec<ast::Entry>, ParseError> {
+    parser::Parser::new(source.chars()).parse()
+}
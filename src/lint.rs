@@ -0,0 +1,365 @@
+//! Warnings about mistakes in L20n resources that parse, but probably
+//! don't do what the translator meant.
+//!
+//! ```rust
+//! let entries = l20n::parse("<_unused 'x'>\n<hi '{{ \"a\" < \"b\" ? 1 : 2 }}'>").unwrap();
+//! let warnings = l20n::lint::lint(&entries);
+//! assert_eq!(warnings.iter().map(|w| w.code()).collect::<Vec<_>>(), vec!["L004", "L001"]);
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use ast::{self, Visitor};
+use context::{self, FileLoader, LoadError, Loader};
+use parser::{ParseError, Parser};
+
+/// A likely mistake in an L20n resource.
+#[derive(Debug, Clone)]
+pub struct Warning {
+    /// The kind of mistake.
+    pub kind: WarningKind,
+    /// Where the mistake is.
+    pub span: ast::Span,
+}
+
+/// The description of the Warning.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WarningKind {
+    /// An operator is applied to values it doesn't accept, such as `<` to
+    /// strings, and will fail with `ResolveErrorKind::WrongType`.
+    WrongOperandType(ast::BinOp),
+    /// An entity whose value is a Hash without a default is looked up with
+    /// `entity.key` or `entity["key"]` for a key it doesn't have. Contains
+    /// the key.
+    UnknownMember(String),
+    /// A Hash without a default is indexed with a key it doesn't have.
+    /// Contains the key.
+    UnknownKey(String),
+    /// A private entity, starting with `_`, is never used. Contains its id.
+    UnusedPrivate(String),
+    /// A macro is never called. Contains its id.
+    UnusedMacro(String),
+}
+
+impl Warning {
+    /// A short code identifying the kind of warning, such as `L001`.
+    pub fn code(&self) -> &'static str {
+        match self.kind {
+            WarningKind::WrongOperandType(_) => "L001",
+            WarningKind::UnknownMember(_) => "L002",
+            WarningKind::UnknownKey(_) => "L003",
+            WarningKind::UnusedPrivate(_) => "L004",
+            WarningKind::UnusedMacro(_) => "L005",
+        }
+    }
+
+    /// A description of the kind of warning.
+    pub fn description(&self) -> &'static str {
+        match self.kind {
+            WarningKind::WrongOperandType(_) => "An operator is applied to values of the wrong type",
+            WarningKind::UnknownMember(_) => "An entity without a default is looked up with a key it does not have",
+            WarningKind::UnknownKey(_) => "A Hash without a default is indexed with a key it does not have",
+            WarningKind::UnusedPrivate(_) => "A private entity is never used",
+            WarningKind::UnusedMacro(_) => "A macro is never called",
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(match self.kind {
+            WarningKind::WrongOperandType(op) => write!(f, "{}: {}", self.description(), op),
+            WarningKind::UnknownMember(ref s) |
+            WarningKind::UnknownKey(ref s) |
+            WarningKind::UnusedPrivate(ref s) |
+            WarningKind::UnusedMacro(ref s) => write!(f, "{}: {}", self.description(), s),
+        });
+        write!(f, " on line {}, column {}", self.span.start.line, self.span.start.col)
+    }
+}
+
+/// Lints the entries of a resource, returning the warnings in the order
+/// they appear.
+pub fn lint(entries: &[ast::Entry]) -> Vec<Warning> {
+    lint_locale(&[entries.to_vec()]).pop().unwrap_or_default()
+}
+
+/// Lints the resources of a locale together, so that a private entity or
+/// macro used by another resource isn't reported as unused. Returns the
+/// warnings of each resource, in the order given.
+pub fn lint_locale(resources: &[Vec<ast::Entry>]) -> Vec<Vec<Warning>> {
+    // The keys of each entity whose value is a Hash without a default, from
+    // the last resource that defines it.
+    let mut hashes = HashMap::new();
+    for entries in resources {
+        for entry in entries {
+            if let ast::Entity(ref id, ref value, _, _) = entry.kind {
+                match value.kind {
+                    ast::Hash(ref map, None, _) => hashes.insert(&id[..], map),
+                    _ => hashes.remove(&id[..])
+                };
+            }
+        }
+    }
+
+    let mut used = HashSet::new();
+    let mut results = vec![];
+    for entries in resources {
+        let mut visitor = LintVisitor {
+            hashes: &hashes,
+            used: used,
+            warnings: vec![],
+        };
+        for entry in entries {
+            visitor.visit_entry(entry);
+        }
+        used = visitor.used;
+        results.push(visitor.warnings);
+    }
+
+    for (entries, warnings) in resources.iter().zip(results.iter_mut()) {
+        for entry in entries {
+            let kind = match entry.kind {
+                ast::Entity(ref id, ..) if id.starts_with('_') && !used.contains(id) => {
+                    WarningKind::UnusedPrivate(id.clone())
+                }
+                ast::Macro(ref id, ..) if !used.contains(id) => WarningKind::UnusedMacro(id.clone()),
+                _ => continue
+            };
+            warnings.push(Warning {
+                kind: kind,
+                span: entry.span,
+            });
+        }
+        warnings.sort_by(|a, b| (a.span.start.line, a.span.start.col).cmp(&(b.span.start.line, b.span.start.col)));
+    }
+    results
+}
+
+/// Lints the resources in files together like `lint_locale`, along with
+/// the resources they import, so that a private entity or macro used only
+/// by an import isn't reported as unused. Returns the warnings of each
+/// file, in the order given. Imported resources aren't linted themselves.
+pub fn lint_files<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<Vec<Warning>>, LoadError> {
+    let mut resources = vec![];
+    let mut imported = vec![];
+    for path in paths {
+        let path = path.as_ref();
+        let source = try!(FileLoader.load(path).map_err(|e| LoadError::Io(path.to_path_buf(), e)));
+        let entries = try!(Parser::new(source.chars()).parse().map_err(|mut e| {
+            e.path = Some(path.to_path_buf());
+            LoadError::Parse(e)
+        }));
+        for entry in &entries {
+            if let ast::Import(ref import) = entry.kind {
+                let env = try!(context::load(&FileLoader, &context::relative_to(path, import), &mut vec![path.to_path_buf()])
+                    .map_err(|e| LoadError::Import(path.to_path_buf(), entry.span.start, Box::new(e))));
+                imported.push(env.into_iter().map(|(_, entry)| entry).collect());
+            }
+        }
+        resources.push(entries);
+    }
+    let count = resources.len();
+    resources.extend(imported);
+    let mut results = lint_locale(&resources);
+    results.truncate(count);
+    Ok(results)
+}
+
+/// Parses and lints a resource.
+pub fn lint_source(source: &str) -> Result<Vec<Warning>, ParseError> {
+    let entries = try!(Parser::new(source.chars()).parse());
+    Ok(lint(&entries))
+}
+
+struct LintVisitor<'a> {
+    hashes: &'a HashMap<&'a str, &'a HashMap<String, ast::Value>>,
+    used: HashSet<String>,
+    warnings: Vec<Warning>,
+}
+
+impl<'a> LintVisitor<'a> {
+    fn warn(&mut self, kind: WarningKind, span: ast::Span) {
+        self.warnings.push(Warning {
+            kind: kind,
+            span: span,
+        });
+    }
+
+    // Checks the keys that literal indices select, level by level.
+    fn check_index(&mut self, value: &ast::Value, index: &[ast::Expr]) {
+        let (map, default) = match value.kind {
+            ast::Hash(ref map, ref default, _) => (map, default),
+            _ => return
+        };
+        let first = match index.first() {
+            Some(first) => first,
+            None => return
+        };
+        if let ast::ValExpr(ast::Value { kind: ast::Str(ref key), .. }) = first.kind {
+            if default.is_none() && !map.contains_key(key) {
+                self.warn(WarningKind::UnknownKey(key.clone()), first.span);
+            }
+        }
+        for member in map.values() {
+            self.check_index(member, &index[1..]);
+        }
+    }
+}
+
+impl<'a> Visitor for LintVisitor<'a> {
+    fn visit_entry(&mut self, entry: &ast::Entry) {
+        if let ast::Entity(_, ref value, ref index, _) = entry.kind {
+            self.check_index(value, index);
+        }
+        ast::walk_entry(self, entry);
+    }
+
+    fn visit_attr(&mut self, attr: &ast::Attr) {
+        self.check_index(&attr.1, &attr.2);
+        ast::walk_attr(self, attr);
+    }
+
+    fn visit_expr(&mut self, expr: &ast::Expr) {
+        match expr.kind {
+            ast::IdentExpr(ref id) => {
+                self.used.insert(id.clone());
+            }
+            ast::PropExpr(ref parent, ref prop, ref access) => {
+                if let (&ast::IdentExpr(ref id), Some(key)) = (&parent.kind, literal_key(prop, access)) {
+                    let unknown = match self.hashes.get(&id[..]) {
+                        Some(map) => !map.contains_key(key),
+                        None => false
+                    };
+                    if unknown {
+                        self.warn(WarningKind::UnknownMember(key.clone()), prop.span);
+                    }
+                }
+            }
+            ast::BinExpr(ref left, op, ref right) => {
+                if let (Some(l), Some(r)) = (type_of(left), type_of(right)) {
+                    if !accepts(op, l, r) {
+                        self.warn(WarningKind::WrongOperandType(op), expr.span);
+                    }
+                }
+            }
+            _ => {}
+        }
+        ast::walk_expr(self, expr);
+    }
+}
+
+// The key of `parent.key` or `parent["key"]`.
+fn literal_key<'a>(prop: &'a ast::Expr, access: &ast::AccessType) -> Option<&'a String> {
+    match (access, &prop.kind) {
+        (&ast::AccessType::Static, &ast::IdentExpr(ref key)) |
+        (&ast::AccessType::Computed, &ast::ValExpr(ast::Value { kind: ast::Str(ref key), .. })) => Some(key),
+        _ => None
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Type {
+    Bool,
+    Num,
+    Str,
+}
+
+// The type an expression always has, if it can be told without resolving
+// it. Integers and floats mix freely, so both are `Num`.
+fn type_of(expr: &ast::Expr) -> Option<Type> {
+    match expr.kind {
+        ast::NumExpr(_) | ast::FloatExpr(_) => Some(Type::Num),
+        ast::ValExpr(ast::Value { kind: ast::Str(_), .. }) |
+        ast::ValExpr(ast::Value { kind: ast::ComplexStr(_), .. }) => Some(Type::Str),
+        ast::ParenExpr(ref expr) => type_of(expr),
+        ast::UnExpr(ast::UnNot, _) => Some(Type::Bool),
+        ast::UnExpr(_, _) => Some(Type::Num),
+        ast::BinExpr(_, op, _) => Some(match op {
+            ast::BiAdd | ast::BiSub | ast::BiMul | ast::BiDiv | ast::BiRem => Type::Num,
+            _ => Type::Bool
+        }),
+        ast::CondExpr(_, ref consequent, ref alt) => match (type_of(consequent), type_of(alt)) {
+            (Some(c), Some(a)) if c == a => Some(c),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+// Mirrors the operands `compiler::binary` accepts.
+fn accepts(op: ast::BinOp, left: Type, right: Type) -> bool {
+    match op {
+        ast::BiAdd | ast::BiSub | ast::BiMul | ast::BiDiv | ast::BiRem |
+        ast::BiLt | ast::BiLe | ast::BiGt | ast::BiGe => left == Type::Num && right == Type::Num,
+        ast::BiAnd | ast::BiOr => left == Type::Bool && right == Type::Bool,
+        ast::BiEq | ast::BiNe => left == right,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::process;
+
+    use super::{lint_files, lint_locale, lint_source, WarningKind};
+    use ast;
+
+    #[test]
+    fn test_lint() {
+        let src = r#"<_used 'x'>
+<_unused 'y'>
+<add($a, $b) { $a + $b }>
+<sub($a, $b) { $a - $b }>
+<cmp '{{ "a" < "b" ? 1 : 0 }} {{ "a" != "b" ? _used : add(1, 2) }} {{ 1 == "1" ? 1 : 0 }} {{ $n < 1 }}'>
+<pick["c"] { a: 'A', b: 'B' }>
+<fine["c"] { a: 'A', *b: 'B' } title["x"]: { y: 'Y' }>
+<brand { short: 'Rust', long: 'The Rust Language' }>
+<about '{{ brand.short }} {{ brand["full"] }} {{ brand.tiny }} {{ fine.c }} {{ brand[$key] }}'>"#;
+        let warnings = lint_source(src).unwrap().into_iter()
+            .map(|w| (w.code(), w.kind, w.span.start.line, w.span.start.col))
+            .collect::<Vec<_>>();
+        assert_eq!(warnings, vec![
+            ("L004", WarningKind::UnusedPrivate(String::from("_unused")), 2, 1),
+            ("L005", WarningKind::UnusedMacro(String::from("sub")), 4, 1),
+            ("L001", WarningKind::WrongOperandType(ast::BiLt), 5, 10),
+            ("L001", WarningKind::WrongOperandType(ast::BiEq), 5, 71),
+            ("L003", WarningKind::UnknownKey(String::from("c")), 6, 7),
+            ("L003", WarningKind::UnknownKey(String::from("x")), 7, 38),
+            ("L002", WarningKind::UnknownMember(String::from("full")), 9, 36),
+            ("L002", WarningKind::UnknownMember(String::from("tiny")), 9, 56),
+        ]);
+    }
+
+    #[test]
+    fn test_lint_locale() {
+        let a = ast_of("<_brand 'Rust'>\n<_other 'x'>\n<os { win: 'Windows' }>");
+        let b = ast_of("<hi 'Hi {{ _brand }} on {{ os.mac }}'>");
+        let results = lint_locale(&[a, b]);
+        assert_eq!(results[0].iter().map(|w| w.kind.clone()).collect::<Vec<_>>(),
+                   vec![WarningKind::UnusedPrivate(String::from("_other"))]);
+        assert_eq!(results[1].iter().map(|w| w.kind.clone()).collect::<Vec<_>>(),
+                   vec![WarningKind::UnknownMember(String::from("mac"))]);
+    }
+
+    #[test]
+    fn test_lint_files() {
+        let dir = env::temp_dir().join(format!("l20n-test-lint-files-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        File::create(dir.join("shared.l20n")).unwrap().write_all(b"<about 'About {{ _brand }}'>").unwrap();
+        File::create(dir.join("main.l20n")).unwrap().write_all(b"import('shared.l20n')\n<_brand 'Rust'>\n<_unused 'x'>").unwrap();
+        let results = lint_files(&[dir.join("main.l20n")]).unwrap();
+        assert_eq!(results[0].iter().map(|w| w.kind.clone()).collect::<Vec<_>>(),
+                   vec![WarningKind::UnusedPrivate(String::from("_unused"))]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn ast_of(source: &str) -> Vec<ast::Entry> {
+        ::parse(source).unwrap()
+    }
+}
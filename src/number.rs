@@ -0,0 +1,242 @@
+use std::str::FromStr;
+
+use fixed_decimal::Decimal;
+use icu_decimal::DecimalFormatter;
+use icu_decimal::options::{DecimalFormatterOptions, GroupingStrategy};
+use icu_experimental::dimension::currency::CurrencyType;
+use icu_experimental::dimension::currency::formatter::CurrencyFormatter;
+use icu_experimental::dimension::percent::formatter::PercentFormatter;
+use icu_locale_core::Locale;
+
+use compiler::{Functions, NativeFn, ResolveError};
+use compiler;
+use data;
+use plural;
+
+/// How a number is presented.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Style {
+    /// A plain number, such as `1,234.5`.
+    Decimal,
+    /// A percentage of the number, so `0.25` is `25%`.
+    Percent,
+    /// An amount in the currency with the given ISO 4217 code.
+    Currency(String),
+}
+
+/// Formats numbers with the CLDR symbols and patterns of a locale.
+///
+/// The data comes from ICU4X, which has CLDR data for the same languages
+/// as the plural rules, and their regional variants. Other languages get
+/// the CLDR root format, such as `1,234.5`.
+#[derive(Debug, Clone)]
+pub struct NumberFormat {
+    locale: Locale,
+    decimal: Option<DecimalFormatter>,
+    fallback: bool,
+}
+
+impl NumberFormat {
+    /// The number format for a language tag. `i-default` gets the CLDR
+    /// root format without grouping, except in amounts of a currency, and
+    /// languages CLDR doesn't know get the CLDR root format.
+    pub fn new(tag: &str) -> NumberFormat {
+        let langid = tag.replace('_', "-");
+        let (locale, options) = match Locale::try_from_str(&langid) {
+            _ if tag == "i-default" => (Locale::UNKNOWN, DecimalFormatterOptions::from(GroupingStrategy::Never)),
+            Ok(locale) => (locale, DecimalFormatterOptions::default()),
+            Err(_) => (Locale::UNKNOWN, DecimalFormatterOptions::default())
+        };
+        NumberFormat {
+            decimal: DecimalFormatter::try_new((&locale).into(), options).ok(),
+            locale: locale,
+            fallback: tag != "i-default" && !plural::is_known(&langid),
+        }
+    }
+
+    /// Whether CLDR doesn't know the language of the tag, so numbers get
+    /// the CLDR root format.
+    pub fn is_fallback(&self) -> bool {
+        self.fallback
+    }
+
+    /// Formats a number as it is interpolated into a string: grouped, and
+    /// rounded to at most 3 fraction digits, the CLDR default that `number`
+    /// also uses.
+    pub fn format_default(&self, n: &data::Data) -> Option<String> {
+        self.format(n, &Style::Decimal, None, Some(3))
+    }
+
+    /// Formats a number in a style, with at least `min` and at most `max`
+    /// fraction digits. Without `max`, fractions are kept as they are.
+    /// Amounts of a currency are then rounded or padded to the currency's
+    /// own number of fraction digits. Returns None if `n` isn't a number,
+    /// or the currency code isn't three letters.
+    pub fn format(&self, n: &data::Data, style: &Style, min: Option<usize>, max: Option<usize>) -> Option<String> {
+        let n = match (style, n) {
+            (&Style::Percent, &data::Num(n)) => match n.checked_mul(100) {
+                Some(n) => data::Num(n),
+                None => data::Float(n as f64 * 100.0)
+            },
+            (&Style::Percent, &data::Float(n)) => data::Float(n * 100.0),
+            (_, n) => n.clone()
+        };
+        let (negative, int, frac) = match digits(&n, min.unwrap_or(0), max) {
+            Some(digits) => digits,
+            None => return None
+        };
+        let mut number = format!("{}{}", if negative { "-" } else { "" }, int);
+        if !frac.is_empty() {
+            number.push('.');
+            number.push_str(&frac);
+        }
+        let (number, decimal) = match (Decimal::from_str(&number), &self.decimal) {
+            (Ok(number), &Some(ref decimal)) => (number, decimal),
+            _ => return None
+        };
+        let locale = &self.locale;
+        match *style {
+            Style::Decimal => Some(decimal.format(&number).to_string()),
+            Style::Percent => {
+                let percent = PercentFormatter::try_new_with_decimal_formatter(locale.into(), decimal.clone(), Default::default());
+                percent.ok().map(|percent| percent.format(&number).to_string())
+            }
+            Style::Currency(ref code) => {
+                let code = match CurrencyType::try_from_str(&code.to_lowercase()) {
+                    Ok(code) => code,
+                    Err(_) => return None
+                };
+                let currency = CurrencyFormatter::try_new_symbol(locale.into(), code, Default::default());
+                currency.ok().map(|currency| currency.format_fixed_decimal(&number).to_string())
+            }
+        }
+    }
+}
+
+// Splits a number into its sign, integer digits and fraction digits,
+// rounding to `max` fraction digits and padding to `min`.
+fn digits(n: &data::Data, min: usize, max: Option<usize>) -> Option<(bool, String, String)> {
+    let (negative, s) = match *n {
+        data::Num(n) => (n < 0, format!("{}", n).trim_left_matches('-').to_string()),
+        data::Float(n) => (n < 0.0, match max {
+            Some(max) => format!("{:.*}", max, n.abs()),
+            None => format!("{}", n.abs())
+        }),
+        _ => return None
+    };
+    let (int, frac) = match s.find('.') {
+        Some(i) => (s[..i].to_string(), s[i + 1..].to_string()),
+        None => (s, String::new())
+    };
+    let mut frac = frac.trim_right_matches('0').to_string();
+    while frac.len() < min {
+        frac.push('0');
+    }
+    let zero = int.chars().all(|c| c == '0') && frac.chars().all(|c| c == '0');
+    Some((negative && !zero, int, frac))
+}
+
+/// Registers the `number` macro, which formats a number for the locale:
+///
+/// ```text
+/// number($n)                       1,234.568
+/// number($n, 'decimal', 2, 2)      1,234.57
+/// number($ratio, 'percent')        25%
+/// number($price, 'currency', 'EUR') €9.99
+/// ```
+///
+/// The optional last two arguments are the minimum and maximum number of
+/// fraction digits. They default to 0 and 3 for decimals and 0 and 0 for
+/// percentages. Amounts of a currency always end up with the currency's
+/// own digits, such as 2 for `EUR` and 0 for `JPY`.
+pub fn register(functions: &mut Functions, format: NumberFormat) {
+    functions.insert("number", number_fn(format));
+}
+
+fn number_fn(format: NumberFormat) -> NativeFn {
+    Box::new(move |args: &[data::Data]| {
+        let (n, rest) = match args.split_first() {
+            Some(split) => split,
+            None => return Err(ResolveError::unplaced(compiler::WrongNumberOfArgs))
+        };
+        let (style, rest) = match rest.split_first() {
+            None => (Style::Decimal, rest),
+            Some((&data::Str(ref style), rest)) => match (&style[..], rest.split_first()) {
+                ("decimal", _) => (Style::Decimal, rest),
+                ("percent", _) => (Style::Percent, rest),
+                ("currency", Some((&data::Str(ref code), _))) if code.len() != 3 || !code.chars().all(|c| c.is_ascii_alphabetic()) => {
+                    return Err(compiler::FunctionFailed(format!("unknown currency '{}'", code)).into())
+                }
+                ("currency", Some((&data::Str(ref code), rest))) => (Style::Currency(code.clone()), rest),
+                ("currency", _) => return Err(ResolveError::unplaced(compiler::WrongNumberOfArgs)),
+                (other, _) => {
+                    return Err(compiler::FunctionFailed(format!("unknown number style '{}'", other)).into())
+                }
+            },
+            Some(_) => return Err(ResolveError::unplaced(compiler::WrongType))
+        };
+        let (min, max) = match style {
+            Style::Decimal => (0, Some(3)),
+            Style::Percent => (0, Some(0)),
+            Style::Currency(_) => (0, None)
+        };
+        let (min, max) = match rest.len() {
+            0 => (min, max),
+            2 => match (&rest[0], &rest[1]) {
+                (&data::Num(min), &data::Num(max)) if 0 <= min && min <= max && max <= 20 => {
+                    (min as usize, Some(max as usize))
+                }
+                _ => return Err(ResolveError::unplaced(compiler::WrongType))
+            },
+            _ => return Err(ResolveError::unplaced(compiler::WrongNumberOfArgs))
+        };
+        match format.format(n, &style, Some(min), max) {
+            Some(s) => Ok(data::Str(s)),
+            None => Err(ResolveError::unplaced(compiler::WrongType))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NumberFormat, Style};
+    use data::{Num, Float};
+
+    #[test]
+    fn test_format_default() {
+        let en = NumberFormat::new("en-US");
+        assert_eq!(en.format_default(&Num(1234567)).unwrap(), "1,234,567");
+        assert_eq!(en.format_default(&Float(-1234.125)).unwrap(), "-1,234.125");
+        assert_eq!(NumberFormat::new("de").format_default(&Float(1234.5)).unwrap(), "1.234,5");
+        assert_eq!(NumberFormat::new("en-IN").format_default(&Num(1234567)).unwrap(), "12,34,567");
+        assert_eq!(NumberFormat::new("pl").format_default(&Num(1234)).unwrap(), "1234");
+        assert_eq!(NumberFormat::new("pl").format_default(&Num(12345)).unwrap(), "12\u{a0}345");
+        assert_eq!(NumberFormat::new("i-default").format_default(&Num(1234567)).unwrap(), "1234567");
+        assert_eq!(NumberFormat::new("de").format_default(&Float(0.1 + 0.2)).unwrap(), "0,3");
+        assert_eq!(en.format_default(&Float(2.0 / 3.0)).unwrap(), "0.667");
+    }
+
+    #[test]
+    fn test_styles() {
+        let en = NumberFormat::new("en");
+        let de = NumberFormat::new("de-DE");
+        let eur = Style::Currency(String::from("EUR"));
+        assert_eq!(en.format(&Float(0.256), &Style::Percent, Some(0), Some(0)).unwrap(), "26%");
+        assert_eq!(de.format(&Float(0.256), &Style::Percent, Some(1), Some(1)).unwrap(), "25,6\u{a0}%");
+        assert_eq!(en.format(&Float(1234.5), &eur, Some(2), Some(2)).unwrap(), "€1,234.50");
+        assert_eq!(de.format(&Float(1234.5), &eur, Some(2), Some(2)).unwrap(), "1.234,50\u{a0}€");
+        assert_eq!(en.format(&Num(5), &Style::Currency(String::from("CHF")), Some(2), Some(2)).unwrap(),
+                   "CHF\u{a0}5.00");
+        assert_eq!(en.format(&Float(-0.0001), &Style::Decimal, Some(0), Some(3)).unwrap(), "0");
+    }
+
+    #[test]
+    fn test_fallback() {
+        assert!(!NumberFormat::new("de-CH").is_fallback());
+        assert!(!NumberFormat::new("i-default").is_fallback());
+        assert!(!NumberFormat::new("ar-EG").is_fallback());
+        let tlh = NumberFormat::new("tlh");
+        assert!(tlh.is_fallback());
+        assert_eq!(tlh.format_default(&Float(1234.5)).unwrap(), "1,234.5");
+    }
+}
@@ -2,20 +2,17 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::error;
 use std::error::Error as _StdError;
 use std::fmt;
+use std::mem;
+use std::path::PathBuf;
 
 pub type Result<T> = ::std::result::Result<T, ParseError>;
 
 pub use self::ParseErrorKind::*;
-pub use self::Entry::*;
-pub use self::Value::*;
-pub use self::AccessType::*;
-pub use self::Expr::*;
-pub use self::BinOp::*;
-pub use self::UnOp::*;
+pub use ast::*;
 
 /// An error occurred trying to parse an L20n resource. The L20n file is
 /// invalid.
@@ -27,6 +24,8 @@ pub struct ParseError {
     pub line: usize,
     /// The column where the error occurred.
     pub col: usize,
+    /// The file the resource was loaded from, if it was.
+    pub path: Option<PathBuf>,
 }
 
 impl error::Error for ParseError {
@@ -45,13 +44,18 @@ impl error::Error for ParseError {
             ParseErrorKind::VarError => "Illegal syntax for a variable",
             ParseErrorKind::StrError => "Illegal syntax for a String",
             ParseErrorKind::HashError => "Illegal syntax for a Hash",
+            ParseErrorKind::ImportError => "Illegal syntax for an import",
         }
     }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} on line {}, column {}", self.description(), self.line, self.col)
+        try!(write!(f, "{} on line {}, column {}", self.description(), self.line, self.col));
+        match self.path {
+            Some(ref path) => write!(f, " in {}", path.display()),
+            None => Ok(())
+        }
     }
 }
 
@@ -84,78 +88,24 @@ pub enum ParseErrorKind {
     StrError,
     /// Illegal syntax for a Hash.
     HashError,
-}
-
-#[derive(Debug, PartialEq, Clone)]
-pub enum Entry {
-    Entity(String, Value, Vec<Expr>, Vec<Attr>),
-    Macro(String, Vec<Expr>, Expr),
-    Comment(String),
-}
-
-#[derive(Debug, PartialEq, Clone)]
-pub enum Value {
-    Str(String),
-    ComplexStr(Vec<Expr>),
-    Hash(HashMap<String, Value>, Option<String>, Option<Box<Expr>>)
-}
-
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub enum AccessType {
-    Computed,
-    Static,
-}
-
-#[derive(Debug, PartialEq, Clone)]
-pub enum Expr {
-    CondExpr(Box<Expr>, Box<Expr>, Box<Expr>),
-    BinExpr(Box<Expr>, BinOp, Box<Expr>),
-    UnExpr(UnOp, Box<Expr>),
-    VarExpr(String),
-    ValExpr(Value),
-    PropExpr(Box<Expr>, Box<Expr>, AccessType),
-    AttrExpr(Box<Expr>, Box<Expr>, AccessType),
-    CallExpr(Box<Expr>, Vec<Expr>),
-    IdentExpr(String),
-    NumExpr(i64),
-    ParenExpr(Box<Expr>),
-    GlobalExpr(String),
-    ThisExpr,
-}
-
-#[derive(Debug, PartialEq, Clone)]
-pub struct Attr(pub String, pub Value, pub Vec<Expr>);
-
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub enum BinOp {
-    BiAdd,
-    BiSub,
-    BiMul,
-    BiDiv,
-    BiRem,
-    BiAnd,
-    BiOr,
-    BiEq,
-    BiNe,
-    BiLt,
-    BiLe,
-    BiGt,
-    BiGe
-}
-
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub enum UnOp {
-    UnAdd,
-    UnSub,
-    UnNot
+    /// Illegal syntax for an import.
+    ImportError,
 }
 
 pub struct Parser<T> {
     reader: T,
     ch: Option<char>,
-    lookahead: Option<char>,
+    lookahead: VecDeque<char>,
     line: usize,
     col: usize,
+    offset: usize,
+    next_offset: usize,
+    // Whether only whitespace comes before the current character on its
+    // line.
+    line_start: bool,
+    // Just past the last character consumed that wasn't whitespace, which is
+    // where the node being parsed ends.
+    last: Pos,
 }
 
 impl<T: Iterator<Item=char>> Parser<T> {
@@ -163,9 +113,38 @@ impl<T: Iterator<Item=char>> Parser<T> {
         Parser {
             reader: source,
             ch: None,
-            lookahead: None,
+            lookahead: VecDeque::new(),
             line: 1, // Technically we'll always start from line 1
             col: 0,  // .. but the line may be empty
+            offset: 0,
+            next_offset: 0,
+            line_start: true,
+            last: Pos::default(),
+        }
+    }
+
+    fn pos(&self) -> Pos {
+        Pos {
+            offset: self.offset,
+            line: self.line,
+            col: self.col,
+        }
+    }
+
+    // The position of the character after the current one, which must not
+    // be a newline.
+    fn next_pos(&self) -> Pos {
+        Pos {
+            offset: self.next_offset,
+            line: self.line,
+            col: self.col + 1,
+        }
+    }
+
+    fn span_from(&self, start: Pos) -> Span {
+        Span {
+            start: start,
+            end: self.last,
         }
     }
 
@@ -174,26 +153,44 @@ impl<T: Iterator<Item=char>> Parser<T> {
             kind: kind,
             line: self.line,
             col: self.col,
+            path: None,
         }
     }
 
     fn bump(&mut self) {
-        match self.lookahead.take() {
+        match self.ch {
+            Some('\n') => {
+                self.line_start = true;
+            }
+            Some(' ') | Some('\t') | Some('\r') | None => {}
+            Some(_) => {
+                self.last = self.next_pos();
+                self.line_start = false;
+            }
+        }
+
+        match self.lookahead.pop_front() {
             None => { self.ch = self.reader.next(); }
             Some(ch) => { self.ch = Some(ch); }
         }
+        self.offset = self.next_offset;
+        if let Some(ch) = self.ch {
+            self.next_offset += ch.len_utf8();
+        }
 
         match self.ch {
             Some('\n') => {
+                // The next character is the first of its line.
                 self.line += 1;
-                self.col = 1;
+                self.col = 0;
             }
             Some('\r') => {
                 // Normalize CRLF to LF.
                 if self.peek() == Some('\n') {
                     self.bump()
+                } else {
+                    self.col += 1;
                 }
-                self.col += 1;
             }
             Some(_) => {
                 self.col += 1;
@@ -203,13 +200,32 @@ impl<T: Iterator<Item=char>> Parser<T> {
     }
 
     fn peek(&mut self) -> Option<char> {
-        match self.lookahead {
-            None => {
-                self.lookahead = self.reader.next();
-                self.lookahead
+        if self.lookahead.is_empty() {
+            if let Some(ch) = self.reader.next() {
+                self.lookahead.push_back(ch);
             }
-            Some(ch) => Some(ch),
         }
+        self.lookahead.front().cloned()
+    }
+
+    // Whether the source continues with `s` from the current character.
+    fn peek_str(&mut self, s: &str) -> bool {
+        let mut chars = s.chars();
+        if self.ch != chars.next() {
+            return false;
+        }
+        for (i, c) in chars.enumerate() {
+            if self.lookahead.len() <= i {
+                match self.reader.next() {
+                    Some(ch) => self.lookahead.push_back(ch),
+                    None => return false
+                }
+            }
+            if self.lookahead[i] != c {
+                return false;
+            }
+        }
+        true
     }
 
     fn ch_is(&self, ch: char) -> bool {
@@ -219,22 +235,70 @@ impl<T: Iterator<Item=char>> Parser<T> {
     pub fn parse(mut self) -> Result<Vec<Entry>> {
         self.bump();
         let mut entries = vec!();
-        loop {
-            self.parse_whitespace();
-            let ch = match self.ch {
-                Some(ch) => ch,
-                None => { break; }
-            };
+        while let Some(entry) = self.parse_next() {
+            entries.push(try!(entry));
+        }
+        Ok(entries)
+    }
 
-            if ch == '<' {
-                entries.push(try!(self.parse_entry()));
-            } else if ch == '/' && self.peek() == Some('*') {
-                entries.push(try!(self.parse_comment()));
-            } else {
-                return Err(self.error(EntryError))
+    /// Parses as much of the source as possible. After an error, the parser
+    /// skips ahead to the next line that starts with an entry or a comment
+    /// and carries on from there, so a single malformed entry only loses
+    /// that entry.
+    ///
+    /// Returns the entries that parsed, and the errors for those that didn't.
+    pub fn parse_partial(mut self) -> (Vec<Entry>, Vec<ParseError>) {
+        self.bump();
+        let mut entries = vec!();
+        let mut errors = vec!();
+        while let Some(entry) = self.parse_next() {
+            match entry {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    errors.push(e);
+                    self.skip_to_next_entry();
+                }
             }
         }
-        Ok(entries)
+        (entries, errors)
+    }
+
+    fn parse_next(&mut self) -> Option<Result<Entry>> {
+        self.parse_whitespace();
+        let ch = self.ch;
+        match ch {
+            Some('<') => Some(self.parse_entry()),
+            Some('/') if self.peek() == Some('*') => Some(self.parse_comment()),
+            Some('i') if self.peek_str("import") => Some(self.parse_import()),
+            Some(_) => Some(Err(self.error(EntryError))),
+            None => None
+        }
+    }
+
+    // Entries can't be told apart from a `<` operator in the middle of a
+    // line, so only an entry or comment at the start of a line counts. The
+    // error may have been found at the start of the next entry, so that is
+    // checked before skipping the rest of the line.
+    fn skip_to_next_entry(&mut self) {
+        loop {
+            if self.line_start {
+                let ch = self.ch;
+                match ch {
+                    Some('<') => return,
+                    Some('/') if self.peek() == Some('*') => return,
+                    Some('i') if self.peek_str("import(") => return,
+                    _ => {}
+                }
+            }
+            loop {
+                match self.ch {
+                    None => return,
+                    Some('\n') => { self.bump(); break; }
+                    Some(_) => self.bump()
+                }
+            }
+            self.parse_whitespace();
+        }
     }
 
     fn parse_whitespace(&mut self) {
@@ -258,6 +322,7 @@ impl<T: Iterator<Item=char>> Parser<T> {
     }
 
     fn parse_entry(&mut self) -> Result<Entry> {
+        let start = self.pos();
         self.bump();
         let id = try!(self.parse_identifier());
         let val = match self.ch {
@@ -269,13 +334,13 @@ impl<T: Iterator<Item=char>> Parser<T> {
         self.parse_whitespace();
         if self.ch_is('>') {
             self.bump();
-            Ok(val)
+            Ok(Entry::new(val, self.span_from(start)))
         } else {
             Err(self.error(EntryError))
         }
     }
 
-    fn parse_macro(&mut self, id: String) -> Result<Entry> {
+    fn parse_macro(&mut self, id: String) -> Result<EntryKind> {
         if id.as_bytes()[0] == b'_' {
             return Err(self.error(MacroError));
         }
@@ -310,7 +375,7 @@ impl<T: Iterator<Item=char>> Parser<T> {
         Ok(Macro(id, args, body))
     }
 
-    fn parse_entity(&mut self, id: String) -> Result<Entry> {
+    fn parse_entity(&mut self, id: String) -> Result<EntryKind> {
         let mut index = vec![];
         if self.ch_is('[') {
             self.bump();
@@ -342,6 +407,7 @@ impl<T: Iterator<Item=char>> Parser<T> {
                 _ => {}
             }
 
+            let start = self.pos();
             let id = try!(self.parse_identifier());
 
             let mut indices = vec![];
@@ -351,6 +417,7 @@ impl<T: Iterator<Item=char>> Parser<T> {
                     indices.push(try!(this.parse_expression()));
                     Ok(())
                 }));
+                self.bump();
             }
 
             self.parse_whitespace();
@@ -361,13 +428,15 @@ impl<T: Iterator<Item=char>> Parser<T> {
             self.parse_whitespace();
 
             let value = try!(self.parse_value());
+            self.parse_whitespace();
 
-            attrs.push(Attr(id, value, indices));
+            attrs.push(Attr(id, value, indices, self.span_from(start)));
         }
         Ok(attrs)
     }
 
     fn parse_comment(&mut self) -> Result<Entry> {
+        let start = self.pos();
         self.bump();
         self.bump();
         let mut s = String::new();
@@ -387,18 +456,55 @@ impl<T: Iterator<Item=char>> Parser<T> {
             }
             self.bump();
         }
-        Ok(Comment(s))
+        Ok(Entry::new(Comment(s), self.span_from(start)))
+    }
+
+    fn parse_import(&mut self) -> Result<Entry> {
+        let start = self.pos();
+        match self.parse_identifier() {
+            Ok(ref id) if id == "import" => {}
+            _ => return Err(self.error(ImportError))
+        }
+        if !self.ch_is('(') {
+            return Err(self.error(ImportError));
+        }
+        self.bump();
+        self.parse_whitespace();
+        let path = match self.ch {
+            Some('"') | Some('\'') => match try!(self.parse_str()) {
+                Str(path) => path,
+                _ => return Err(self.error(ImportError))
+            },
+            _ => return Err(self.error(ImportError))
+        };
+        self.parse_whitespace();
+        if !self.ch_is(')') {
+            return Err(self.error(ImportError));
+        }
+        self.bump();
+        Ok(Entry::new(Import(path), self.span_from(start)))
     }
 
     fn parse_value(&mut self) -> Result<Value> {
-        match self.ch {
+        let start = self.pos();
+        let kind = try!(match self.ch {
             Some('"') | Some('\'') => self.parse_tristr_or_str(),
             Some('{') => self.parse_hash(),
             _ => Err(self.error(ValueError))
+        });
+        Ok(Value::new(kind, self.span_from(start)))
+    }
+
+    // A literal part of a string, as an expression.
+    fn literal(&self, s: String, start: Pos) -> Expr {
+        let mut span = self.span_from(start);
+        if span.end.offset < start.offset {
+            span.end = start;
         }
+        Expr::new(ValExpr(Value::new(Str(s), span)), span)
     }
 
-    fn parse_tristr_or_str(&mut self) -> Result<Value> {
+    fn parse_tristr_or_str(&mut self) -> Result<ValueKind> {
         let quote = self.ch.unwrap();
         if self.peek() == Some(quote) {
             self.bump();
@@ -406,6 +512,7 @@ impl<T: Iterator<Item=char>> Parser<T> {
                 self.bump();
                 self.parse_tristr()
             } else {
+                self.bump();
                 Ok(Str("".to_string()))
             }
         } else {
@@ -413,7 +520,7 @@ impl<T: Iterator<Item=char>> Parser<T> {
         }
     }
 
-    fn parse_tristr(&mut self) -> Result<Value> {
+    fn parse_tristr(&mut self) -> Result<ValueKind> {
         let mut s = String::new();
 
         let quote = self.ch.unwrap();
@@ -424,11 +531,13 @@ impl<T: Iterator<Item=char>> Parser<T> {
         // If the quote is immediately followed by a newline, skip it and remove indentation.
         self.parse_crlf();
         self.parse_horizontal_whitespace();
+        let mut start = self.pos();
 
         loop {
             match self.ch {
                 Some(c@'{') => {
                     if self.peek() == Some('{') {
+                        let literal = self.literal(mem::replace(&mut s, String::new()), start);
                         self.bump();
                         self.bump();
                         self.parse_whitespace();
@@ -437,9 +546,9 @@ impl<T: Iterator<Item=char>> Parser<T> {
                         if self.ch_is('}') && self.peek() == Some('}') {
                             self.bump();
                             self.bump();
-                            exprs.push(ValExpr(Str(s)));
+                            exprs.push(literal);
                             exprs.push(expr);
-                            s = String::new();
+                            start = self.pos();
                         } else {
                             return Err(self.error(ValueError));
                         }
@@ -486,8 +595,9 @@ impl<T: Iterator<Item=char>> Parser<T> {
         }
 
         if exprs.len() > 0 {
+            let s = s.trim_right();
             if s.len() > 0 {
-                exprs.push(ValExpr(Str(s.trim_right().into())));
+                exprs.push(self.literal(s.into(), start));
             }
             Ok(ComplexStr(exprs))
         } else {
@@ -495,17 +605,19 @@ impl<T: Iterator<Item=char>> Parser<T> {
         }
     }
 
-    fn parse_str(&mut self) -> Result<Value> {
+    fn parse_str(&mut self) -> Result<ValueKind> {
         let mut s = String::new();
 
         let quote = self.ch.unwrap();
         let mut exprs = vec![];
+        let mut start = self.next_pos();
 
         loop {
             self.bump();
             match self.ch {
                 Some(c@'{') => {
                     if self.peek() == Some('{') {
+                        let literal = self.literal(mem::replace(&mut s, String::new()), start);
                         self.bump();
                         self.bump();
                         self.parse_whitespace();
@@ -513,9 +625,9 @@ impl<T: Iterator<Item=char>> Parser<T> {
                         self.parse_whitespace();
                         if self.ch_is('}') && self.peek() == Some('}') {
                             self.bump();
-                            exprs.push(ValExpr(Str(s)));
+                            exprs.push(literal);
                             exprs.push(expr);
-                            s = String::new();
+                            start = self.next_pos();
                         } else {
                             return Err(self.error(ValueError));
                         }
@@ -532,7 +644,7 @@ impl<T: Iterator<Item=char>> Parser<T> {
 
         if exprs.len() > 0 {
             if s.len() > 0 {
-                exprs.push(ValExpr(Str(s)));
+                exprs.push(self.literal(s, start));
             }
             Ok(ComplexStr(exprs))
         } else {
@@ -540,7 +652,7 @@ impl<T: Iterator<Item=char>> Parser<T> {
         }
     }
 
-    fn parse_hash(&mut self) -> Result<Value> {
+    fn parse_hash(&mut self) -> Result<ValueKind> {
         self.bump();
         self.parse_whitespace();
 
@@ -613,6 +725,7 @@ impl<T: Iterator<Item=char>> Parser<T> {
     }
 
     fn parse_cond_expression(&mut self) -> Result<Expr> {
+        let start = self.pos();
         let cond = try!(self.parse_or_expression());
         self.parse_whitespace();
 
@@ -631,11 +744,13 @@ impl<T: Iterator<Item=char>> Parser<T> {
         self.parse_whitespace();
         let alternate = try!(self.parse_expression());
 
-        Ok(CondExpr(Box::new(cond), Box::new(consequent), Box::new(alternate)))
+        let kind = CondExpr(Box::new(cond), Box::new(consequent), Box::new(alternate));
+        Ok(Expr::new(kind, self.span_from(start)))
     }
 
     fn parse_prefix_expression<F>(&mut self, ops: &[BinOp], mut next: F) -> Result<Expr>
     where F: FnMut(&mut Parser<T>) -> Result<Expr> {
+        let start = self.pos();
         let mut exp = try!(next(self));
         loop {
             self.parse_whitespace();
@@ -658,7 +773,7 @@ impl<T: Iterator<Item=char>> Parser<T> {
 
             self.parse_whitespace();
             let right = try!(next(self));
-            exp = BinExpr(Box::new(exp), binop, Box::new(right));
+            exp = Expr::new(BinExpr(Box::new(exp), binop, Box::new(right)), self.span_from(start));
         }
         Ok(exp)
     }
@@ -677,10 +792,12 @@ impl<T: Iterator<Item=char>> Parser<T> {
             None => return next(self),
             Some(op) => op
         };
+        let start = self.pos();
         self.bump();
         self.parse_whitespace();
 
-        Ok(UnExpr(unop, Box::new(try!(self.parse_postfix_expression(ops, next)))))
+        let expr = try!(self.parse_postfix_expression(ops, next));
+        Ok(Expr::new(UnExpr(unop, Box::new(expr)), self.span_from(start)))
     }
 
     fn peek_bin_op(&mut self, op: &BinOp) -> usize {
@@ -785,15 +902,19 @@ impl<T: Iterator<Item=char>> Parser<T> {
                 return Err(self.error(ExprError));
             }
             self.bump();
-            Ok(PropExpr(Box::new(accessed), Box::new(exp), Computed))
+            let span = self.span_from(accessed.span.start);
+            Ok(Expr::new(PropExpr(Box::new(accessed), Box::new(exp), Computed), span))
         } else {
+            let start = self.pos();
             let exp = try!(self.parse_identifier());
-            Ok(PropExpr(Box::new(accessed), Box::new(IdentExpr(exp)), Static))
+            let exp = Expr::new(IdentExpr(exp), self.span_from(start));
+            let span = self.span_from(accessed.span.start);
+            Ok(Expr::new(PropExpr(Box::new(accessed), Box::new(exp), Static), span))
         }
     }
 
     fn parse_attr_expression(&mut self, accessed: Expr) -> Result<Expr> {
-        match accessed {
+        match accessed.kind {
             ParenExpr(..) | IdentExpr(..) | ThisExpr => {},
             _ => return Err(self.error(AttrError))
         }
@@ -809,26 +930,34 @@ impl<T: Iterator<Item=char>> Parser<T> {
             }
             self.bump();
 
-            Ok(AttrExpr(Box::new(accessed), Box::new(exp), Computed))
+            let span = self.span_from(accessed.span.start);
+            Ok(Expr::new(AttrExpr(Box::new(accessed), Box::new(exp), Computed), span))
         } else {
-            Ok(AttrExpr(Box::new(accessed), Box::new(try!(self.parse_expression())), Static))
+            let exp = try!(self.parse_expression());
+            let span = self.span_from(accessed.span.start);
+            Ok(Expr::new(AttrExpr(Box::new(accessed), Box::new(exp), Static), span))
         }
     }
 
     fn parse_call_expression(&mut self, callee: Expr) -> Result<Expr> {
         self.bump(); // (
+        self.parse_whitespace();
         let mut args = vec![];
 
-        try!(self.parse_list(')', CallError, |this| {
-            args.push(try!(this.parse_expression()));
-            Ok(())
-        }));
+        if !self.ch_is(')') {
+            try!(self.parse_list(')', CallError, |this| {
+                args.push(try!(this.parse_expression()));
+                Ok(())
+            }));
+        }
         self.bump(); // )
 
-        Ok(CallExpr(Box::new(callee), args))
+        let span = self.span_from(callee.span.start);
+        Ok(Expr::new(CallExpr(Box::new(callee), args), span))
     }
 
     fn parse_paren_expression(&mut self) -> Result<Expr> {
+        let start = self.pos();
         match self.ch {
             Some('(') => self.bump(),
             _ => return self.parse_primary_expression()
@@ -843,32 +972,35 @@ impl<T: Iterator<Item=char>> Parser<T> {
             _ => return Err(self.error(ParenError))
         }
 
-        Ok(ParenExpr(Box::new(exp)))
+        Ok(Expr::new(ParenExpr(Box::new(exp)), self.span_from(start)))
     }
 
     fn parse_primary_expression(&mut self) -> Result<Expr> {
-        match self.ch {
+        let start = self.pos();
+        let kind = match self.ch {
             Some(c) => {
                 match c {
-                    '0'...'9' => self.parse_number(),
-                    '\'' | '"' | '{' | '[' => Ok(ValExpr(try!(self.parse_value()))),
-                    '$' => self.parse_variable(),
+                    '0'...'9' => return self.parse_number(),
+                    '\'' | '"' | '{' | '[' => ValExpr(try!(self.parse_value())),
+                    '$' => return self.parse_variable(),
                     '@' => {
                         self.bump();
-                        Ok(GlobalExpr(try!(self.parse_identifier())))
+                        GlobalExpr(try!(self.parse_identifier()))
                     },
                     '~' => {
                         self.bump();
-                        Ok(ThisExpr)
+                        ThisExpr
                     },
-                    _ => Ok(IdentExpr(try!(self.parse_identifier())))
+                    _ => IdentExpr(try!(self.parse_identifier()))
                 }
             },
-            None => Err(self.error(ExprError))
-        }
+            None => return Err(self.error(ExprError))
+        };
+        Ok(Expr::new(kind, self.span_from(start)))
     }
 
     fn parse_number(&mut self) -> Result<Expr> {
+        let start = self.pos();
         let mut num = String::new();
         loop {
             match self.ch {
@@ -883,19 +1015,38 @@ impl<T: Iterator<Item=char>> Parser<T> {
             }
         }
 
-        if num.len() > 0 {
-            Ok(NumExpr(num.parse().unwrap()))
-        } else {
-            Err(self.error(ExprError))
+        // A decimal point only belongs to the number if a digit follows it.
+        let is_float = self.ch_is('.') && match self.peek() {
+            Some('0'...'9') => true,
+            _ => false
+        };
+        if !is_float {
+            return match num.parse() {
+                Ok(n) => Ok(Expr::new(NumExpr(n), self.span_from(start))),
+                Err(_) => Err(self.error(ExprError))
+            };
+        }
+
+        num.push('.');
+        self.bump();
+        while let Some(ch @ '0'...'9') = self.ch {
+            num.push(ch);
+            self.bump();
+        }
+        match num.parse() {
+            Ok(n) => Ok(Expr::new(FloatExpr(n), self.span_from(start))),
+            Err(_) => Err(self.error(ExprError))
         }
     }
 
     fn parse_variable(&mut self) -> Result<Expr> {
+        let start = self.pos();
         if !self.ch_is('$') {
             return Err(self.error(VarError));
         }
         self.bump();
-        Ok(VarExpr(try!(self.parse_identifier())))
+        let name = try!(self.parse_identifier());
+        Ok(Expr::new(VarExpr(name), self.span_from(start)))
     }
 
     fn parse_identifier(&mut self) -> Result<String> {
@@ -927,80 +1078,106 @@ impl<T: Iterator<Item=char>> Parser<T> {
 
 #[cfg(test)]
 mod tests {
-    use super::{Parser, Entity, Str, Hash, Attr, VarExpr, Macro, CondExpr,
-                            BinExpr, ValExpr, ComplexStr, NumExpr, BiGt, BiGe, Comment};
+    use super::{Parser, Entry, EntryKind, Value, ValueKind, Expr, ExprKind, Span, Entity, Str,
+                            Hash, Attr, VarExpr, Macro, CondExpr, BinExpr, ValExpr, ComplexStr,
+                            NumExpr, FloatExpr, BiGt, BiGe, BiMul, PropExpr, IdentExpr, Static,
+                            Comment, Import, ImportError};
     use std::collections::HashMap;
 
+    use ast::without_spans;
+
     fn s(v: &'static str) -> String {
         String::from(v)
     }
 
+    // Expected trees are built with empty spans, and compared with parsed
+    // ones through `without_spans`.
+    fn e(kind: EntryKind) -> Entry {
+        Entry::new(kind, Span::default())
+    }
+
+    fn v(kind: ValueKind) -> Value {
+        Value::new(kind, Span::default())
+    }
+
+    fn x(kind: ExprKind) -> Expr {
+        Expr::new(kind, Span::default())
+    }
+
+    fn str_(val: &'static str) -> Value {
+        v(Str(s(val)))
+    }
+
+    fn str_expr(val: &'static str) -> Expr {
+        x(ValExpr(str_(val)))
+    }
+
     #[test]
     fn test_basic_entity() {
         let p = Parser::new("<hello \"Hello, World\" >".chars());
-        assert_eq!(p.parse().unwrap(), vec![
-                             Entity(s("hello"), Str(s("Hello, World")), vec![], vec![])
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Entity(s("hello"), str_("Hello, World"), vec![], vec![]))
         ]);
     }
 
     #[test]
     fn test_multiple_entities() {
         let p = Parser::new("<hell0 \"Hello, World\">\n<bye 'Bye!'>".chars());
-        assert_eq!(p.parse().unwrap(), vec![
-                             Entity(s("hell0"), Str(s("Hello, World")), vec![], vec![]),
-                             Entity(s("bye"), Str(s("Bye!")), vec![], vec![])
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Entity(s("hell0"), str_("Hello, World"), vec![], vec![])),
+                             e(Entity(s("bye"), str_("Bye!"), vec![], vec![]))
         ]);
     }
 
     #[test]
     fn test_oneline_triquote_entity() {
         let p = Parser::new(r#"<hello """Hello"", "World""" >"#.chars());
-        assert_eq!(p.parse().unwrap(), vec![
-                             Entity(s("hello"), Str(s(r#"Hello"", "World"#)), vec![], vec![])
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Entity(s("hello"), str_(r#"Hello"", "World"#), vec![], vec![]))
         ]);
     }
 
     #[test]
     fn test_multiline_triquote_entity() {
         let p = Parser::new("<hello \"\"\"\n  Hello\"\", \n  \"World\n\"\"\">".chars());
-        assert_eq!(p.parse().unwrap(), vec![
-                             Entity(s("hello"), Str(s("Hello\"\", \n\"World")), vec![], vec![])
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Entity(s("hello"), str_("Hello\"\", \n\"World"), vec![], vec![]))
         ]);
     }
 
     #[test]
     fn test_normalize() {
         let p = Parser::new("<hello \"\"\"\r\n  Hello\"\", \r\n  \"World\r\n\"\"\">".chars());
-        assert_eq!(p.parse().unwrap(), vec![
-                             Entity(s("hello"), Str(s("Hello\"\", \n\"World")), vec![], vec![])
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Entity(s("hello"), str_("Hello\"\", \n\"World"), vec![], vec![]))
         ]);
     }
 
     #[test]
     fn test_macro() {
         let p = Parser::new("<foo($n) { $n > 1 ? 'foo' : 'bar' }>".chars());
-        assert_eq!(p.parse().unwrap(), vec![
-                             Macro(s("foo"),
-                                         vec![VarExpr(s("n"))],
-                                         CondExpr(Box::new(BinExpr(Box::new(VarExpr(s("n"))), BiGt, Box::new(NumExpr(1)))),
-                                                            Box::new(ValExpr(Str(s("foo")))),
-                                                            Box::new(ValExpr(Str(s("bar"))))
-                                         )
-                             ),
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Macro(s("foo"),
+                                         vec![x(VarExpr(s("n")))],
+                                         x(CondExpr(Box::new(x(BinExpr(Box::new(x(VarExpr(s("n")))), BiGt, Box::new(x(NumExpr(1)))))),
+                                                            Box::new(str_expr("foo")),
+                                                            Box::new(str_expr("bar"))
+                                         ))
+                             )),
         ]);
     }
 
     #[test]
     fn test_ge() {
         let p = Parser::new("<foo($n) { $n >= 1 ? 'foo' : 'bar' }>".chars());
-        assert_eq!(p.parse().unwrap(), vec![
-                             Macro(s("foo"),
-                                         vec![VarExpr(s("n"))],
-                                         CondExpr(Box::new(BinExpr(Box::new(VarExpr(s("n"))), BiGe, Box::new(NumExpr(1)))),
-                                                            Box::new(ValExpr(Str(s("foo")))),
-                                                            Box::new(ValExpr(Str(s("bar"))))
-                                         )
-                             ),
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Macro(s("foo"),
+                                         vec![x(VarExpr(s("n")))],
+                                         x(CondExpr(Box::new(x(BinExpr(Box::new(x(VarExpr(s("n")))), BiGe, Box::new(x(NumExpr(1)))))),
+                                                            Box::new(str_expr("foo")),
+                                                            Box::new(str_expr("bar"))
+                                         ))
+                             )),
         ]);
     }
 
@@ -1008,10 +1185,10 @@ mod tests {
     fn test_hash() {
         let p = Parser::new("<pro { masculine: 'his', feminine: 'her'}>".chars());
         let mut map = HashMap::new();
-        map.insert(s("masculine"), Str(s("his")));
-        map.insert(s("feminine"), Str(s("her")));
-        assert_eq!(p.parse().unwrap(), vec![
-                             Entity(s("pro"), Hash(map, None, None), vec![], vec![])
+        map.insert(s("masculine"), str_("his"));
+        map.insert(s("feminine"), str_("her"));
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Entity(s("pro"), v(Hash(map, None, None)), vec![], vec![]))
         ]);
     }
 
@@ -1019,10 +1196,10 @@ mod tests {
     fn test_hash_default() {
         let p = Parser::new("<pro { *masculine: 'his', feminine: 'her'}>".chars());
         let mut map = HashMap::new();
-        map.insert(s("masculine"), Str(s("his")));
-        map.insert(s("feminine"), Str(s("her")));
-        assert_eq!(p.parse().unwrap(), vec![
-                             Entity(s("pro"), Hash(map, Some(s("masculine")), None), vec![], vec![])
+        map.insert(s("masculine"), str_("his"));
+        map.insert(s("feminine"), str_("her"));
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Entity(s("pro"), v(Hash(map, Some(s("masculine")), None)), vec![], vec![]))
         ]);
     }
 
@@ -1030,37 +1207,144 @@ mod tests {
     fn test_hash_index() {
         let p = Parser::new("<pro['feminine'] { masculine: 'his', feminine: 'her'}>".chars());
         let mut map = HashMap::new();
-        map.insert(s("masculine"), Str(s("his")));
-        map.insert(s("feminine"), Str(s("her")));
-        assert_eq!(p.parse().unwrap(), vec![
-                             Entity(s("pro"), Hash(map, None, None), vec![ValExpr(Str(s("feminine")))], vec![])
+        map.insert(s("masculine"), str_("his"));
+        map.insert(s("feminine"), str_("her"));
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Entity(s("pro"), v(Hash(map, None, None)), vec![str_expr("feminine")], vec![]))
         ]);
     }
 
     #[test]
     fn test_attr() {
         let p = Parser::new("<pro 'her' neuter: 'their'>".chars());
-        assert_eq!(p.parse().unwrap(), vec![
-                             Entity(s("pro"), Str(s("her")), vec![], vec![Attr(s("neuter"), Str(s("their")), vec![])])
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Entity(s("pro"), str_("her"), vec![],
+                                      vec![Attr(s("neuter"), str_("their"), vec![], Span::default())]))
+        ]);
+    }
+
+    #[test]
+    fn test_attr_index() {
+        let p = Parser::new("<pro 'her' gender[$n]: { one: 'their' } other: 'x'>".chars());
+        let mut map = HashMap::new();
+        map.insert(s("one"), str_("their"));
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Entity(s("pro"), str_("her"), vec![], vec![
+                                 Attr(s("gender"), v(Hash(map, None, None)), vec![x(VarExpr(s("n")))], Span::default()),
+                                 Attr(s("other"), str_("x"), vec![], Span::default())
+                             ]))
+        ]);
+    }
+
+    #[test]
+    fn test_empty_str() {
+        let p = Parser::new("<empty ''>\n<next \"\" a: ''>".chars());
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Entity(s("empty"), str_(""), vec![], vec![])),
+                             e(Entity(s("next"), str_(""), vec![],
+                                      vec![Attr(s("a"), str_(""), vec![], Span::default())]))
         ]);
     }
 
     #[test]
     fn test_complex_str() {
         let p = Parser::new("<hi 'Hello, {{ $name }}!'>".chars());
-        assert_eq!(p.parse().unwrap(), vec![
-                             Entity(s("hi"), ComplexStr(vec![
-                                 ValExpr(Str(s("Hello, "))),
-                                 VarExpr(s("name")),
-                                 ValExpr(Str(s("!")))
-                                 ]), vec![], vec![])
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Entity(s("hi"), v(ComplexStr(vec![
+                                 str_expr("Hello, "),
+                                 x(VarExpr(s("name"))),
+                                 str_expr("!")
+                                 ])), vec![], vec![]))
                              ]);
     }
 
     #[test]
     fn test_comment() {
         let p = Parser::new("/* foo bar */".chars());
-        assert_eq!(p.parse().unwrap(), vec![Comment(s(" foo bar "))])
+        assert_eq!(without_spans(p.parse().unwrap()), vec![e(Comment(s(" foo bar ")))])
+    }
+
+    #[test]
+    fn test_import() {
+        let p = Parser::new("import('shared/brand.l20n')\n<hi 'Hi'>".chars());
+        assert_eq!(without_spans(p.parse().unwrap()), vec![e(Import(s("shared/brand.l20n"))), e(Entity(s("hi"), str_("Hi"), vec![], vec![]))]);
+        let p = Parser::new("import('{{ $x }}')".chars());
+        assert_eq!(p.parse().unwrap_err().kind, ImportError);
+        let p = Parser::new("imports('a')".chars());
+        assert_eq!(p.parse().unwrap_err().kind, ImportError);
+    }
+
+    #[test]
+    fn test_float() {
+        let p = Parser::new("<price($n) { 1.50 * $n.max }>".chars());
+        assert_eq!(without_spans(p.parse().unwrap()), vec![
+                             e(Macro(s("price"),
+                                         vec![x(VarExpr(s("n")))],
+                                         x(BinExpr(Box::new(x(FloatExpr(1.5))), BiMul,
+                                                   Box::new(x(PropExpr(Box::new(x(VarExpr(s("n")))),
+                                                                       Box::new(x(IdentExpr(s("max")))), Static)))))
+                             ))
+        ]);
+        assert_eq!(format!("{}", x(FloatExpr(2.0))), "2.0");
+    }
+
+    #[test]
+    fn test_display() {
+        let src = r#"<hi "Hello, {{ $user.name }}! {{ brand::long }} {{ plural($n * 2) == "one" ? ~ : @hour }}">"#;
+        let p = Parser::new(src.chars());
+        match p.parse().unwrap().pop().map(|entry| entry.kind) {
+            Some(Entity(_, ref value, _, _)) => assert_eq!(format!("<hi {}>", value), src),
+            other => panic!("unexpected entry {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_parse_partial() {
+        let src = "<a 'A'>\n<b 'B' oops\n  more junk>\n/* c */\n<d { x: 1 < 2 ? 'y' : 'z' } .>\n<e 'E'>";
+        let (entries, errors) = Parser::new(src.chars()).parse_partial();
+        assert_eq!(without_spans(entries), vec![
+                             e(Entity(s("a"), str_("A"), vec![], vec![])),
+                             e(Comment(s(" c "))),
+                             e(Entity(s("e"), str_("E"), vec![], vec![]))
+        ]);
+        assert_eq!(errors.iter().map(|e| e.line).collect::<Vec<_>>(), vec![3, 5]);
+    }
+
+    #[test]
+    fn test_parse_partial_missing_close() {
+        let (entries, errors) = Parser::new("<a 'A'\n<b 'B'>\n<c 'C'>".chars()).parse_partial();
+        assert_eq!(without_spans(entries), vec![
+                             e(Entity(s("b"), str_("B"), vec![], vec![])),
+                             e(Entity(s("c"), str_("C"), vec![], vec![]))
+        ]);
+        assert_eq!(errors.iter().map(|e| (e.line, e.col)).collect::<Vec<_>>(), vec![(2, 1)]);
+    }
+
+    #[test]
+    fn test_parse_partial_import() {
+        let src = "<a { one: 'x' :\n  inbox: 'y' }>\nimport('b.l20n')\n<b 'B'>";
+        let (entries, errors) = Parser::new(src.chars()).parse_partial();
+        assert_eq!(without_spans(entries), vec![
+                             e(Import(s("b.l20n"))),
+                             e(Entity(s("b"), str_("B"), vec![], vec![]))
+        ]);
+        assert_eq!(errors.iter().map(|e| e.line).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn test_spans() {
+        let p = Parser::new("/* c */\n<hi 'Hi, {{ $name }}'>".chars());
+        let entries = p.parse().unwrap();
+        let entry = &entries[1];
+        assert_eq!((entry.span.start.offset, entry.span.start.line, entry.span.start.col), (8, 2, 1));
+        assert_eq!(entry.span.end.offset, 30);
+        match entry.kind {
+            Entity(_, Value { kind: ComplexStr(ref exprs), .. }, _, _) => {
+                let span = exprs[1].span;
+                assert_eq!((span.start.line, span.start.col, span.end.col), (2, 13, 18));
+            },
+            ref other => panic!("unexpected entry {:?}", other)
+        }
     }
 
 }
@@ -0,0 +1,107 @@
+use intl_pluralrules::{PluralCategory, PluralRuleType, PluralRules};
+use unic_langid::LanguageIdentifier;
+
+use compiler::{Functions, NativeFn, ResolveError};
+use compiler;
+use data;
+
+/// Registers the CLDR plural rules for the language `tag` as the `plural`
+/// (cardinal) and `ordinal` macros. Both take a number and return its
+/// plural category, one of `zero`, `one`, `two`, `few`, `many` or `other`,
+/// which can be used to index a Hash:
+///
+/// ```text
+/// <unread[plural($n)] { one: '{{ $n }} message', *other: '{{ $n }} messages' }>
+/// ```
+pub fn register(functions: &mut Functions, tag: &str) {
+    functions.insert("plural", category_fn(tag, PluralRuleType::CARDINAL));
+    functions.insert("ordinal", category_fn(tag, PluralRuleType::ORDINAL));
+}
+
+/// Whether CLDR has plural rules for the language of `tag`, which is also
+/// what decides whether it has number formats.
+pub fn is_known(tag: &str) -> bool {
+    rules(tag, PluralRuleType::CARDINAL).is_some()
+}
+
+// Tags without rules of their own, such as `de-AT`, use the rules of their
+// language. Unknown languages and `i-default` get none, which is the CLDR
+// root locale where every number is `other`.
+fn rules(tag: &str, kind: PluralRuleType) -> Option<PluralRules> {
+    let langid: LanguageIdentifier = match tag.parse() {
+        Ok(langid) => langid,
+        Err(_) => return None
+    };
+    match PluralRules::create(langid.clone(), kind) {
+        Ok(rules) => Some(rules),
+        Err(_) => {
+            let language = LanguageIdentifier::from_parts(langid.language, None, None, &[]);
+            PluralRules::create(language, kind).ok()
+        }
+    }
+}
+
+fn category_fn(tag: &str, kind: PluralRuleType) -> NativeFn {
+    let rules = rules(tag, kind);
+    Box::new(move |args: &[data::Data]| {
+        if args.len() != 1 {
+            return Err(ResolveError::unplaced(compiler::WrongNumberOfArgs));
+        }
+        let category = match (&rules, &args[0]) {
+            (&Some(ref rules), &data::Num(n)) => match rules.select(n) {
+                Ok(category) => category,
+                Err(_) => return Err(ResolveError::unplaced(compiler::WrongType))
+            },
+            (&Some(ref rules), &data::Float(n)) => match rules.select(n) {
+                Ok(category) => category,
+                Err(_) => return Err(ResolveError::unplaced(compiler::WrongType))
+            },
+            (&None, &data::Num(_)) | (&None, &data::Float(_)) => PluralCategory::OTHER,
+            _ => return Err(ResolveError::unplaced(compiler::WrongType))
+        };
+        Ok(data::Str(String::from(match category {
+            PluralCategory::ZERO => "zero",
+            PluralCategory::ONE => "one",
+            PluralCategory::TWO => "two",
+            PluralCategory::FEW => "few",
+            PluralCategory::MANY => "many",
+            PluralCategory::OTHER => "other",
+        })))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::register;
+    use compiler::{compile, Functions, Globals, Resolve, ResolveContext};
+    use data::{Null, Str};
+
+    fn categories(tag: &str, f: &str, ns: &[i64]) -> Vec<String> {
+        let mut functions = Functions::new();
+        register(&mut functions, tag);
+        let globals = Globals::new();
+        let data = Null;
+        ns.iter().map(|n| {
+            let map = compile(&format!("<x '{{{{ {}({}) }}}}'>", f, n)).unwrap();
+            let ctx = ResolveContext::new(&map, &globals, &data).with_functions(&functions);
+            match map["x"].resolve_data(&ctx) {
+                Ok(Str(s)) => s,
+                other => panic!("unexpected {:?}", other)
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn test_cardinal() {
+        assert_eq!(categories("en-US", "plural", &[0, 1, 2]), vec!["other", "one", "other"]);
+        assert_eq!(categories("pl", "plural", &[1, 3, 5, 22]), vec!["one", "few", "many", "few"]);
+        assert_eq!(categories("ar", "plural", &[0, 2, 11, 100]), vec!["zero", "two", "many", "other"]);
+        assert_eq!(categories("i-default", "plural", &[1]), vec!["other"]);
+    }
+
+    #[test]
+    fn test_ordinal() {
+        assert_eq!(categories("en", "ordinal", &[1, 2, 3, 4, 11, 21]),
+                   vec!["one", "two", "few", "other", "other", "one"]);
+    }
+}
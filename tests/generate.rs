@@ -0,0 +1,38 @@
+extern crate l20n;
+
+use std::collections::HashMap;
+
+// The output of `l20n::build::generate` for `messages.l20n`, as a build
+// script would include it.
+mod messages {
+    include!("generate/messages.rs");
+}
+
+const SOURCE: &'static str = include_str!("generate/messages.l20n");
+
+#[test]
+fn test_generated_is_current() {
+    assert_eq!(l20n::build::generate(SOURCE).unwrap(), include_str!("generate/messages.rs"));
+}
+
+#[test]
+fn test_generated_accessors() {
+    let mut locale = l20n::Locale::with_tag("en-US");
+    locale.add_resource(SOURCE).unwrap();
+
+    assert_eq!(messages::brand_name(&locale).unwrap(), "Rust");
+
+    let unread = |count| messages::unread(&locale, messages::UnreadArgs {
+        count: count,
+        user_name: String::from("Ferris"),
+    }).unwrap();
+    assert_eq!(unread(1.0), "Ferris has 1 unread message");
+    assert_eq!(unread(3.0), "Ferris has 3 unread messages");
+
+    let mut user = HashMap::new();
+    user.insert(String::from("name"), l20n::Data::Str(String::from("Ferris")));
+    let profile = messages::profile(&locale, messages::ProfileArgs {
+        user: l20n::Data::Map(user),
+    });
+    assert_eq!(profile.unwrap(), "Ferris (Rust)");
+}
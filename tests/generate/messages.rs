@@ -0,0 +1,35 @@
+// Generated from an L20n resource by l20n::build. Do not edit.
+
+/// Formats `brandName`.
+pub fn brand_name(locale: &::l20n::Locale) -> ::l20n::LocalizeResult<String> {
+    locale.format("brandName", ())
+}
+
+/// The variables `profile` uses.
+pub struct ProfileArgs {
+    /// `$user`
+    pub user: ::l20n::Data,
+}
+
+/// Formats `profile`.
+pub fn profile(locale: &::l20n::Locale, args: ProfileArgs) -> ::l20n::LocalizeResult<String> {
+    let mut data = ::std::collections::HashMap::new();
+    data.insert(String::from("user"), args.user);
+    locale.format("profile", ::l20n::Data::Map(data))
+}
+
+/// The variables `unread` uses.
+pub struct UnreadArgs {
+    /// `$count`
+    pub count: f64,
+    /// `$userName`
+    pub user_name: String,
+}
+
+/// Formats `unread`.
+pub fn unread(locale: &::l20n::Locale, args: UnreadArgs) -> ::l20n::LocalizeResult<String> {
+    let mut data = ::std::collections::HashMap::new();
+    data.insert(String::from("count"), ::l20n::Data::Float(args.count));
+    data.insert(String::from("userName"), ::l20n::Data::Str(args.user_name));
+    locale.format("unread", ::l20n::Data::Map(data))
+}